@@ -0,0 +1,338 @@
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::{Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Router, middleware};
+use axum_extra::extract::cookie::CookieJar;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower::{Layer, ServiceExt};
+use tower_http::compression::CompressionLayer;
+use tower_http::services::ServeDir;
+
+use crate::config::Config;
+use crate::handlers::*;
+use crate::models::redb_store::RedbStore;
+use crate::models::schema;
+use crate::models::store::Store;
+use crate::models::types::{Ex, Result};
+use crate::token::Token;
+use redb::Database;
+
+/// everything a request handler can see
+pub struct Site {
+    pub config: Config,
+    pub store: Arc<dyn Store>,
+    /// rendered home and page views
+    pub cache: PageCache,
+    /// pages being edited together over websockets
+    pub collab: Rooms,
+    /// signs session cookies, new on every start
+    pub token_secret: [u8; 32],
+}
+
+impl Site {
+    pub fn new(config: Config, store: Arc<dyn Store>) -> Self {
+        let token_secret = rand::random();
+        let cache = PageCache::new(config.page_cache_entries, config.page_cache_bytes);
+        Self {
+            config,
+            store,
+            cache,
+            collab: Rooms::default(),
+            token_secret,
+        }
+    }
+}
+
+/// note on top of a redb database, migrated to the current schema
+///
+/// the router can be served as is or mounted with `Router::nest`, as long as
+/// `base_url` and `cookie_path` point at the mount path; a nested site's
+/// robots.txt isn't where crawlers look, serve `robots_txt` at the root
+pub fn app(config: Config, db: Arc<Database>) -> Result<Router> {
+    schema::migrate(&db)?;
+    let store = Arc::new(RedbStore::new(db));
+    Ok(router(Arc::new(Site::new(config, store))))
+}
+
+/// several independent sites in one router, picked by `Host` and path prefix
+///
+/// each site keeps its own database, secrets and session cookies; requests for
+/// an unknown host go to the sites without a `host`, if any; "/robots.txt"
+/// covers every site of the host
+pub fn sites(sites: Vec<(Config, Arc<Database>)>) -> Result<Router> {
+    // host -> (prefix, config, router) of that host's sites, longest prefix first
+    let mut hosts: HashMap<Option<String>, Vec<(String, Config, Router)>> = HashMap::new();
+    for (config, db) in sites {
+        let host = config.host.as_ref().map(|host| host.to_ascii_lowercase());
+        let prefix = config.prefix.clone().unwrap_or_default();
+        let site = app(config.clone(), db)?;
+        hosts.entry(host).or_default().push((prefix, config, site));
+    }
+    for routes in hosts.values_mut() {
+        routes.sort_by_key(|(prefix, _, _)| std::cmp::Reverse(prefix.len()));
+    }
+
+    let hosts = Arc::new(hosts);
+    Ok(Router::new().fallback(move |mut request: Request| {
+        let hosts = hosts.clone();
+        async move {
+            let host = request_host(&request);
+            let routes = hosts.get(&host).or_else(|| hosts.get(&None));
+            if request.uri().path() == "/robots.txt"
+                && let Some(routes) = routes
+            {
+                let configs: Vec<&Config> = routes.iter().map(|(_, config, _)| config).collect();
+                return robots_txt(&configs);
+            }
+            let site = routes
+                .into_iter()
+                .flatten()
+                .find(|(prefix, _, _)| strip_prefix(&mut request, prefix));
+            match site {
+                Some((_, _, site)) => site.clone().oneshot(request).await.into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            }
+        }
+    }))
+}
+
+/// drop `prefix` from the request path, if it is under `prefix`
+fn strip_prefix(request: &mut Request, prefix: &str) -> bool {
+    let path = request.uri().path();
+    let Some(rest) = path.strip_prefix(prefix) else {
+        return false;
+    };
+    if !(rest.is_empty() || rest.starts_with('/')) {
+        return false;
+    }
+    if prefix.is_empty() {
+        return true;
+    }
+
+    let rest = if rest.is_empty() { "/" } else { rest };
+    let uri = match request.uri().query() {
+        Some(query) => format!("{rest}?{query}"),
+        None => rest.to_string(),
+    };
+    match uri.parse() {
+        Ok(uri) => {
+            *request.uri_mut() = uri;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// lowercase host name without port, from the uri (http/2) or `Host` header
+fn request_host(request: &Request) -> Option<String> {
+    let host = match request.uri().host() {
+        Some(host) => host,
+        None => request.headers().get(header::HOST)?.to_str().ok()?,
+    };
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host,
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+/// every route, middleware and the static fallback, served for `site`
+pub fn router(site: Arc<Site>) -> Router {
+    // home page & work space
+    let app = Router::new().route("/", get(home_page));
+
+    let app = app // probes (no auth required)
+        .route("/healthz", get(healthz)) // json
+        .route("/readyz", get(readyz)); // json
+
+    let app = app // auth
+        .route("/auth", get(auth_page)) // html or redirect
+        .route("/auth/sign-out", get(sign_out_handler)) // [] -> cookie
+        .route("/auth/sign-in", post(sign_in_handler)) // [user, passwd] -> cookie
+        .route("/auth/sign-up", post(sign_up_handler)) // [user, passwd, invite_code] -> cookie
+        .route("/invite/{invite_code}", get(invite_handler)); // html or redirect
+
+    let app = app // crawlers
+        .route("/sitemap.xml", get(sitemap)) // xml
+        .route("/robots.txt", get(robots)); // text
+
+    let app = app // feeds
+        .route("/feed.atom", get(site_atom)) // xml
+        .route("/feed.rss", get(site_rss)) // xml
+        .route("/@{user}/feed.atom", get(user_atom)) // xml
+        .route("/@{user}/feed.rss", get(user_rss)); // xml
+
+    let app = app // tags
+        .route("/tags", get(tags_page)) // html
+        .route("/tags/{tag}", get(tag_page)); // html
+
+    let app = app // series
+        .route("/series/{user}/{series}", get(series_page)); // html
+
+    let app = app // user
+        .route("/@{user}", get(user_page)); // html
+
+    let app = app // page
+        .route("/@{user}/{*page}", get(page_route)) // html (page, folder or editor)
+        .route("/page/{user}/{*page}", put(page_create)) // [] -> ok
+        .route("/page/{user}/{*page}", post(page_update)) // [title, markdown] -> ok
+        .route("/page/{user}/{*page}", delete(page_delete)) // [] -> ok
+        .route("/rename/{user}/{*page}", post(page_rename)) // [user, file] -> ok
+        .route("/preview", post(page_preview)) // [markdown] -> html
+        .route("/draft/{user}/{*page}", post(draft_save)) // [title, markdown] -> ok
+        .route("/draft/{user}/{*page}", delete(draft_discard)) // [] -> ok
+        .route("/collab/{user}/{*page}", get(page_collab)); // websocket
+
+    // uploads are limited by config, not by the 2 MB default of axum
+    let limit = DefaultBodyLimit::max(site.config.max_attachment_bytes);
+    let app = app // attachments
+        .route("/files/{user}/{*path}", get(attachment_view)) // file
+        .route("/files/{user}/{*path}", put(attachment_upload).layer(limit)) // bytes -> ok
+        .route("/files/{user}/{*path}", delete(attachment_delete)) // [] -> ok
+        .route("/image/{user}/{*page}", post(image_upload).layer(limit)); // bytes -> markdown
+
+    let app = app // trash
+        .route("/trash/{user}", get(trash_view)) // html
+        .route("/trash/{user}/{deleted_at}/{*page}", post(trash_restore)) // [] -> ok
+        .route("/trash/{user}/{deleted_at}/{*page}", delete(trash_purge)); // [] -> ok
+
+    let app = app // admin
+        .route("/admin/backup", post(admin_backup)) // [] -> json
+        .route("/admin/fsck", get(admin_fsck).post(admin_fsck_repair)) // [] -> json
+        .route("/admin/rerender", post(admin_rerender)) // [] -> json
+        .route("/admin/cache", get(admin_cache)); // [] -> json
+
+    let files = middleware::from_fn_with_state(site.clone(), static_dir_middleware)
+        .layer(ServeDir::new(&site.config.site_root));
+    let app = app
+        .fallback_service(files)
+        .layer(middleware::from_fn_with_state(
+            site.clone(),
+            auth_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            site.clone(),
+            error_middleware,
+        ))
+        .layer(CompressionLayer::new().zstd(true).gzip(true).deflate(true))
+        .with_state(site.clone());
+
+    // urls are normalized before routing, so every route is registered once
+    Router::new()
+        .fallback_service(app)
+        .layer(middleware::from_fn_with_state(
+            site,
+            trailing_slash_middleware,
+        ))
+}
+
+/// one url per resource: no trailing slash, except for the home page and
+/// static directories (served as their index.html)
+///
+/// pages (GET, HEAD) are redirected to the canonical url, api calls are served
+/// either way; only urls with a trailing slash are looked up in `site_root`,
+/// the rest is left to `static_dir_middleware`
+pub async fn trailing_slash_middleware(
+    State(site): State<Arc<Site>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if path.len() > 1 && path.ends_with('/') {
+        if let Some(dir) = static_dir(&site.config.site_root, path).await {
+            if path == format!("/{dir}/") || !is_read(&request) {
+                return next.run(request).await;
+            }
+            return redirect_to_dir(&site, dir, &request);
+        }
+
+        let path = match path.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        };
+        let query = request.uri().query().map(|q| format!("?{q}"));
+        let query = query.unwrap_or_default();
+
+        if is_read(&request) {
+            let path = path.trim_start_matches('/');
+            let location = format!("{}{path}{query}", site.config.base_url);
+            return Redirect::permanent(&location).into_response();
+        }
+        if let Ok(uri) = format!("{path}{query}").parse() {
+            *request.uri_mut() = uri;
+        }
+    }
+    next.run(request).await
+}
+
+/// static directories asked for without their trailing slash are redirected
+/// to it, in front of the static files only so routes never touch the disk
+pub async fn static_dir_middleware(
+    State(site): State<Arc<Site>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if is_read(&request)
+        && !request.uri().path().ends_with('/')
+        && let Some(dir) = static_dir(&site.config.site_root, request.uri().path()).await
+    {
+        return redirect_to_dir(&site, dir, &request);
+    }
+    next.run(request).await
+}
+
+fn is_read(request: &Request) -> bool {
+    matches!(*request.method(), Method::GET | Method::HEAD)
+}
+
+/// to "/{dir}/", keeping the query
+fn redirect_to_dir(site: &Site, dir: &str, request: &Request) -> Response {
+    let query = request.uri().query().map(|q| format!("?{q}"));
+    let location = format!(
+        "{}{dir}/{}",
+        site.config.base_url,
+        query.unwrap_or_default()
+    );
+    Redirect::permanent(&location).into_response()
+}
+
+/// "docs", for "/docs" or "/docs/" when `site_root/docs` is a directory
+async fn static_dir<'a>(site_root: &str, path: &'a str) -> Option<&'a str> {
+    let dir = path.trim_matches('/');
+    let plain = dir.split('/').all(|part| !matches!(part, "" | "." | ".."));
+    let metadata = match plain {
+        true => tokio::fs::metadata(std::path::Path::new(site_root).join(dir)).await,
+        false => return None,
+    };
+    metadata.is_ok_and(|m| m.is_dir()).then_some(dir)
+}
+
+pub async fn auth_middleware(
+    State(site): State<Arc<Site>>,
+    jar: CookieJar,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let auth: Option<String> = jar
+        .get(&site.config.cookie_name())
+        .and_then(|cookie| Token::parse(cookie.value(), site.token_secret));
+
+    request.extensions_mut().insert(auth);
+    next.run(request).await
+}
+
+/// render the error page for any `Ex` a handler returned
+pub async fn error_middleware(
+    State(site): State<Arc<Site>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    match response.extensions().get::<Ex>() {
+        Some(ex) => ex.render(&site.config),
+        None => response,
+    }
+}
@@ -0,0 +1,98 @@
+use crate::config::Config;
+use crate::handlers::cache::CacheStats;
+use crate::models::fsck::{self, Report};
+use crate::models::types::{AppState, Ex, Result};
+use crate::models::{backup, pages};
+use axum::Json;
+use axum::extract::{Extension, State};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct RerenderInfo {
+    pages: usize,
+}
+
+#[derive(Serialize)]
+pub struct BackupInfo {
+    path: String,
+    rows: u64,
+}
+
+/// only configured admins may use /admin endpoints
+fn check_admin(config: &Config, auth: Option<String>) -> Result<()> {
+    match auth {
+        Some(user) if config.is_admin(&user) => Ok(()),
+        _ => Err(Ex::PermissionDenied),
+    }
+}
+
+/// api: write a snapshot into the backup directory
+pub async fn admin_backup(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+) -> Result<Json<BackupInfo>> {
+    check_admin(&site.config, auth)?;
+    let db = site.store.redb().ok_or(Ex::NotSupported)?;
+
+    let (path, rows) = tokio::task::spawn_blocking(move || {
+        let path = backup::backup(db.as_ref(), &site.config)?;
+        let rows = backup::verify(&path)?;
+        Ok::<_, Ex>((path, rows))
+    })
+    .await
+    .map_err(|_| Ex::InternalServerError)??;
+
+    Ok(Json(BackupInfo {
+        path: path.display().to_string(),
+        rows,
+    }))
+}
+
+/// api: report inconsistencies between users and pages
+pub async fn admin_fsck(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+) -> Result<Json<Report>> {
+    check_admin(&site.config, auth)?;
+    let db = site.store.redb().ok_or(Ex::NotSupported)?;
+    let report = tokio::task::spawn_blocking(move || fsck::check(&db, false))
+        .await
+        .map_err(|_| Ex::InternalServerError)??;
+    Ok(Json(report))
+}
+
+/// api: report and repair inconsistencies between users and pages
+pub async fn admin_fsck_repair(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+) -> Result<Json<Report>> {
+    check_admin(&site.config, auth)?;
+    let db = site.store.redb().ok_or(Ex::NotSupported)?;
+    let report = tokio::task::spawn_blocking(move || fsck::check(&db, true))
+        .await
+        .map_err(|_| Ex::InternalServerError)??;
+    site.cache.clear();
+    println!("Repaired database: {report:?}");
+    Ok(Json(report))
+}
+
+/// api: re-render every page produced by an older markdown pipeline
+pub async fn admin_rerender(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+) -> Result<Json<RerenderInfo>> {
+    check_admin(&site.config, auth)?;
+    let db = site.store.redb().ok_or(Ex::NotSupported)?;
+    let pages = pages::rerender_all(&db)?;
+    site.cache.clear();
+    Ok(Json(RerenderInfo { pages }))
+}
+
+/// api: size and hit rate of the rendered-page cache
+pub async fn admin_cache(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+) -> Result<Json<CacheStats>> {
+    check_admin(&site.config, auth)?;
+    Ok(Json(site.cache.stats()))
+}
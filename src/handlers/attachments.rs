@@ -0,0 +1,131 @@
+use crate::handlers::cache::Freshness;
+use crate::models::types::{AppState, Ex, Result};
+use crate::models::{attachments, images};
+use axum::Json;
+use axum::body::Bytes;
+use axum::extract::{Extension, Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+
+/// "notes/shot.png" -> ("notes", "shot.png"), the page and the attachment name
+fn split_path(path: &str) -> Result<(&str, &str)> {
+    match path.rsplit_once('/') {
+        Some((file, name)) if attachments::validate_name(name) => Ok((file, name)),
+        _ => Err(Ex::InvalidFilename),
+    }
+}
+
+/// attached file, with its sniffed type
+pub async fn attachment_view(
+    State(site): AppState,
+    Path((user, path)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let (file, name) = split_path(&path)?;
+    let attachment = site
+        .store
+        .attachment(&user, file, name)?
+        .ok_or(Ex::PageNotFound)?;
+
+    let freshness = Freshness::new(
+        attachment.date,
+        &[attachment.mime.as_bytes(), &attachment.bytes],
+    );
+    let headers_out = freshness.headers(&site.config.cache_control);
+    if freshness.is_fresh(&headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers_out).into_response());
+    }
+
+    // anything that isn't an image, a pdf or text is a download
+    let disposition = match attachment.mime.as_str() {
+        "application/octet-stream" => format!("attachment; filename=\"{name}\""),
+        _ => format!("inline; filename=\"{name}\""),
+    };
+    let content = [
+        (header::CONTENT_TYPE, attachment.mime),
+        (header::CONTENT_DISPOSITION, disposition),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+    Ok((headers_out, content, attachment.bytes).into_response())
+}
+
+/// api: attach a file to a page, the body is the file
+pub async fn attachment_upload(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, path)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<()> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+    let (file, name) = split_path(&path)?;
+
+    site.store.attach(&auth_user, &user, file, name, &body)?;
+    println!(
+        "Attached file: @{user}/{file}/{name} ({} bytes)",
+        body.len()
+    );
+    Ok(())
+}
+
+/// api: delete an attached file
+pub async fn attachment_delete(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, path)): Path<(String, String)>,
+) -> Result<()> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+    let (file, name) = split_path(&path)?;
+
+    site.store.detach(&auth_user, &user, file, name)?;
+    println!("Deleted file: @{user}/{file}/{name}");
+    Ok(())
+}
+
+/// api: attach a pasted or dropped image, cleaned up and scaled down, and
+/// return the markdown showing it
+pub async fn image_upload(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, file)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Json<String>> {
+    // check before decoding anything
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+    site.store.check_edit(&auth_user, &user)?;
+
+    let upload = tokio::task::spawn_blocking(move || images::process(&body))
+        .await
+        .map_err(|_| Ex::InternalServerError)??;
+    let url = |name: &str| format!("{}files/{user}/{file}/{name}", site.config.base_url);
+    let name = upload.name();
+    // names are content hashes, an image uploaded before is already there
+    let existed = site.store.attachment(&user, &file, &name)?.is_some();
+    site.store
+        .attach(&auth_user, &user, &file, &name, &upload.bytes)?;
+    let markdown = match (upload.thumbnail_name(), &upload.thumbnail) {
+        (Some(thumbnail_name), Some((_, thumbnail))) => {
+            // no image without its thumbnail
+            if let Err(ex) = site
+                .store
+                .attach(&auth_user, &user, &file, &thumbnail_name, thumbnail)
+            {
+                if !existed {
+                    let _ = site.store.detach(&auth_user, &user, &file, &name);
+                }
+                return Err(ex);
+            }
+            format!("[![]({})]({})", url(&thumbnail_name), url(&name))
+        }
+        _ => format!("![]({})", url(&name)),
+    };
+    println!("Attached image: @{user}/{file}/{name}");
+    Ok(Json(markdown))
+}
@@ -0,0 +1,363 @@
+use crate::models::pages::{self, Sort};
+use axum::body::Bytes;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::response::{Html, IntoResponse, Response};
+use base64::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// cache validators of a response (ETag and Last-Modified)
+#[derive(Clone)]
+pub(crate) struct Freshness {
+    etag: String,
+    last_modified: SystemTime,
+}
+
+impl Freshness {
+    /// `date` is when the content last changed, `parts` is everything the
+    /// response is rendered from
+    pub(crate) fn new(date: i64, parts: &[impl AsRef<[u8]>]) -> Self {
+        use sha3::{Digest, Sha3_256};
+        let mut hasher = Sha3_256::new();
+        hasher.update(date.to_le_bytes());
+        for part in parts.iter().map(AsRef::as_ref) {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+        let hash = BASE64_URL_SAFE_NO_PAD.encode(&hasher.finalize()[..16]);
+
+        // weak, the compression layer changes the bytes
+        let etag = format!("W/\"{hash}\"");
+        let last_modified = SystemTime::UNIX_EPOCH + Duration::from_secs(date.max(0) as u64);
+        Self {
+            etag,
+            last_modified,
+        }
+    }
+
+    /// the client already has this version (answer with 304)
+    pub(crate) fn is_fresh(&self, headers: &HeaderMap) -> bool {
+        let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+
+        // If-None-Match wins over If-Modified-Since
+        if let Some(tags) = headers.get(header::IF_NONE_MATCH) {
+            let Ok(tags) = tags.to_str() else {
+                return false;
+            };
+            let etag = weak(&self.etag);
+            return tags
+                .split(',')
+                .any(|tag| tag.trim() == "*" || weak(tag) == etag);
+        }
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|since| httpdate::parse_http_date(since.to_str().ok()?).ok())
+            .is_some_and(|since| self.last_modified <= since)
+    }
+
+    /// ETag, Last-Modified and Cache-Control, for both 200 and 304
+    pub(crate) fn headers(&self, cache_control: &str) -> [(HeaderName, HeaderValue); 3] {
+        let value = |v: &str| HeaderValue::from_str(v).unwrap_or(HeaderValue::from_static(""));
+        [
+            (header::ETAG, value(&self.etag)),
+            (
+                header::LAST_MODIFIED,
+                value(&httpdate::fmt_http_date(self.last_modified)),
+            ),
+            (header::CACHE_CONTROL, value(cache_control)),
+        ]
+    }
+}
+
+/// rendered responses, least recently used out first
+///
+/// keyed by route, plus the signed-in user where the page depends on it
+pub struct PageCache {
+    max_entries: usize,
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    /// the home page shows the user's invite code
+    Home {
+        auth: Option<String>,
+        sort: Sort,
+        after: Option<String>,
+    },
+    /// page views look the same for everyone
+    Page { user: String, file: String },
+}
+
+#[derive(Clone)]
+pub(crate) struct Cached {
+    pub(crate) body: Bytes,
+    pub(crate) freshness: Option<Freshness>,
+    /// (user, file) of the next page linked from this one
+    pub(crate) next: Option<(String, String)>,
+    /// links follow a series rather than key order
+    pub(crate) series: bool,
+}
+
+impl Cached {
+    /// 304 if the client has this version already, the page otherwise
+    pub(crate) fn respond(self, headers: &HeaderMap, cache_control: &str) -> Response {
+        match &self.freshness {
+            Some(freshness) if freshness.is_fresh(headers) => {
+                (StatusCode::NOT_MODIFIED, freshness.headers(cache_control)).into_response()
+            }
+            Some(freshness) => (freshness.headers(cache_control), Html(self.body)).into_response(),
+            None => Html(self.body).into_response(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    entries: usize,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<CacheKey, (u64, Instant, Cached)>,
+    // use tick -> key, oldest first
+    lru: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: usize,
+    // bumped by every invalidation, see `insert`
+    generation: u64,
+}
+
+// home pages carry an invite code, don't hand one out for too long
+const MAX_AGE: Duration = Duration::from_secs(3600);
+
+impl PageCache {
+    /// `max_entries` 0 disables the cache
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            inner: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<Cached> {
+        let mut inner = self.inner.lock().unwrap();
+        let cached = match inner.entries.get(key) {
+            Some((_, created, _)) if created.elapsed() > MAX_AGE => {
+                inner.remove(key);
+                None
+            }
+            Some((tick, _, cached)) => Some((*tick, cached.clone())),
+            None => None,
+        };
+        let Some((tick, cached)) = cached else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        // move to the back of the line
+        inner.tick += 1;
+        let now = inner.tick;
+        inner.lru.remove(&tick);
+        inner.lru.insert(now, key.clone());
+        inner.entries.get_mut(key).unwrap().0 = now;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(cached)
+    }
+
+    /// take `generation()` before reading what `cached` is rendered from, so a
+    /// write that lands in between can't leave an outdated page behind
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, cached: Cached, generation: u64) {
+        let size = Inner::size(&key, &cached);
+        if self.max_entries == 0 || size > self.max_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation != generation {
+            return;
+        }
+
+        inner.remove(&key);
+        inner.tick += 1;
+        let now = inner.tick;
+        inner.bytes += size;
+        inner.lru.insert(now, key.clone());
+        inner.entries.insert(key, (now, Instant::now(), cached));
+
+        while inner.entries.len() > self.max_entries || inner.bytes > self.max_bytes {
+            let Some((_, oldest)) = inner.lru.pop_first() else {
+                break;
+            };
+            inner.remove(&oldest);
+        }
+    }
+
+    /// (user, file) was created, updated or deleted
+    ///
+    /// drops the page, every home page, the page linking to it as "next", the
+    /// pages above it (they list what's under them) and the user's series pages
+    pub fn invalidate_page(&self, user: &str, file: &str) {
+        let written = (user, file);
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        let stale: Vec<CacheKey> = inner
+            .entries
+            .iter()
+            .filter(|(key, (_, _, cached))| match key {
+                CacheKey::Home { .. } => true,
+                CacheKey::Page { user, file } => {
+                    let page = (user.as_str(), file.as_str());
+                    let next = cached.next.as_ref().map(|(u, f)| (u.as_str(), f.as_str()));
+                    let key_order =
+                        !cached.series && page < written && next.is_none_or(|next| next >= written);
+                    page == written
+                        || (user == written.0
+                            && (key_order || cached.series || pages::is_under(written.1, file)))
+                }
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        stale.iter().for_each(|key| inner.remove(key));
+    }
+
+    /// drop everything, after writes that don't go through page handlers
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let generation = inner.generation + 1;
+        *inner = Inner {
+            generation,
+            ..Default::default()
+        };
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Inner {
+    fn size(key: &CacheKey, cached: &Cached) -> usize {
+        let key = match key {
+            CacheKey::Home { auth, after, .. } => {
+                auth.as_ref().map_or(0, String::len) + after.as_ref().map_or(0, String::len)
+            }
+            CacheKey::Page { user, file } => user.len() + file.len(),
+        };
+        key + cached.body.len()
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((tick, _, cached)) = self.entries.remove(key) {
+            self.lru.remove(&tick);
+            self.bytes -= Self::size(key, &cached);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(user: &str, file: &str) -> CacheKey {
+        CacheKey::Page {
+            user: user.to_string(),
+            file: file.to_string(),
+        }
+    }
+
+    fn home() -> CacheKey {
+        CacheKey::Home {
+            auth: None,
+            sort: Sort::Date,
+            after: None,
+        }
+    }
+
+    fn cached(body: &str, next: Option<(&str, &str)>) -> Cached {
+        Cached {
+            body: Bytes::from(body.to_string()),
+            freshness: None,
+            next: next.map(|(u, f)| (u.to_string(), f.to_string())),
+            series: false,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = PageCache::new(2, 1024);
+        let generation = cache.generation();
+        cache.insert(page("a", "1"), cached("1", None), generation);
+        cache.insert(page("a", "2"), cached("2", None), generation);
+        assert!(cache.get(&page("a", "1")).is_some());
+        cache.insert(page("a", "3"), cached("3", None), generation);
+        assert!(cache.get(&page("a", "2")).is_none());
+        assert!(cache.get(&page("a", "1")).is_some());
+
+        // too big for the byte limit
+        let cache = PageCache::new(8, 8);
+        cache.insert(page("a", "1"), cached("123456789", None), generation);
+        assert!(cache.get(&page("a", "1")).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 0, 1));
+    }
+
+    #[test]
+    fn invalidates_page_home_and_neighbour() {
+        let cache = PageCache::new(16, 1024);
+        let generation = cache.generation();
+        cache.insert(page("a", "1"), cached("", Some(("a", "3"))), generation);
+        cache.insert(page("a", "3"), cached("", None), generation);
+        cache.insert(page("b", "1"), cached("", None), generation);
+        cache.insert(home(), cached("", None), generation);
+        let series = Cached {
+            series: true,
+            ..cached("", None)
+        };
+        cache.insert(page("a", "9"), series.clone(), generation);
+        cache.insert(page("b", "9"), series, generation);
+
+        // a new page between a/1 and a/3
+        cache.invalidate_page("a", "2");
+        assert!(cache.get(&page("a", "1")).is_none());
+        assert!(cache.get(&page("a", "3")).is_some());
+        assert!(cache.get(&page("b", "1")).is_some());
+        assert!(cache.get(&home()).is_none());
+        // series links don't follow key order, only the user's series pages go
+        assert!(cache.get(&page("a", "9")).is_none());
+        assert!(cache.get(&page("b", "9")).is_some());
+
+        // renders started before the write are dropped
+        cache.insert(page("a", "1"), cached("old", None), generation);
+        assert!(cache.get(&page("a", "1")).is_none());
+
+        // "next" stays within a user
+        cache.invalidate_page("b", "0");
+        assert!(cache.get(&page("a", "3")).is_some());
+        assert!(cache.get(&page("b", "1")).is_some());
+        cache.invalidate_page("b", "1");
+        assert!(cache.get(&page("b", "1")).is_none());
+    }
+}
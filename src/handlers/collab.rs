@@ -0,0 +1,411 @@
+use crate::app::Site;
+use crate::handlers::page::validate_title;
+use crate::models::collab::{Document, MAX_DOCUMENT_CHARS, Op};
+use crate::models::types::{AppState, Ex, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Extension, Path, State};
+use axum::http::{HeaderMap, header};
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// how often a room writes its text back to the page
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// events a connection may fall behind on before it is dropped (and reconnects)
+const ROOM_BACKLOG: usize = 256;
+
+/// (from, at) of a cursor or selection, in chars
+type Cursor = (usize, usize);
+
+/// what an editor sends
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMessage {
+    /// `op` made on revision `rev`, and where it left the cursor
+    Edit {
+        rev: u64,
+        op: Op,
+        cursor: Cursor,
+    },
+    Cursor {
+        cursor: Cursor,
+    },
+    Title {
+        title: String,
+    },
+}
+
+/// what an editor is sent, in the order edits were applied
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage {
+    /// first message of a connection, `id` is the editor's own
+    Init {
+        id: u64,
+        rev: u64,
+        title: String,
+        markdown: String,
+        peers: Vec<PeerInfo>,
+    },
+    /// the editor's own edit was applied as revision `rev`
+    Ack {
+        rev: u64,
+    },
+    /// someone else's edit, applied as revision `rev`
+    Edit {
+        id: u64,
+        rev: u64,
+        op: Op,
+    },
+    Cursor {
+        id: u64,
+        user: String,
+        cursor: Cursor,
+    },
+    Title {
+        id: u64,
+        title: String,
+    },
+    Leave {
+        id: u64,
+    },
+    /// a message that wasn't taken, or a save that failed
+    Error {
+        title: &'static str,
+        message: &'static str,
+    },
+}
+
+#[derive(Clone, Serialize)]
+struct PeerInfo {
+    id: u64,
+    user: String,
+    cursor: Cursor,
+}
+
+impl ServerMessage {
+    /// the message as `id` gets it, if at all
+    fn to(self, id: u64) -> Option<Self> {
+        match self {
+            ServerMessage::Edit { id: from, rev, .. } if from == id => {
+                Some(ServerMessage::Ack { rev })
+            }
+            ServerMessage::Cursor { id: from, .. } | ServerMessage::Title { id: from, .. }
+                if from == id =>
+            {
+                None
+            }
+            message => Some(message),
+        }
+    }
+
+    fn error(ex: Ex) -> Self {
+        let (_, title, message) = ex.describe();
+        ServerMessage::Error { title, message }
+    }
+}
+
+/// pages being edited together, by (user, file)
+#[derive(Default)]
+pub struct Rooms {
+    rooms: Mutex<HashMap<(String, String), Arc<Room>>>,
+    next_id: AtomicU64,
+}
+
+struct Room {
+    user: String,
+    file: String,
+    state: Mutex<RoomState>,
+    events: broadcast::Sender<ServerMessage>,
+    /// held while writing to the store, so saves land in order
+    saving: Mutex<()>,
+}
+
+struct RoomState {
+    doc: Document,
+    title: String,
+    /// connection id -> (editor, cursor)
+    peers: BTreeMap<u64, (String, Cursor)>,
+    /// the editor of the last change not yet saved, the page is saved as them
+    unsaved: Option<String>,
+}
+
+impl Rooms {
+    /// connect `editor` to the room of (user, file), opening it from the
+    /// stored page if nobody is editing it yet
+    fn join(
+        &self,
+        site: &Arc<Site>,
+        user: &str,
+        file: &str,
+        editor: &str,
+    ) -> Result<(
+        Arc<Room>,
+        u64,
+        broadcast::Receiver<ServerMessage>,
+        ServerMessage,
+    )> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let key = (user.to_string(), file.to_string());
+        let room = match rooms.get(&key) {
+            Some(room) => room.clone(),
+            None => {
+                let page = site.store.page(user, file)?.ok_or(Ex::PageNotFound)?;
+                let room = Arc::new(Room {
+                    user: user.to_string(),
+                    file: file.to_string(),
+                    state: Mutex::new(RoomState {
+                        doc: Document::new(&page.markdown),
+                        title: page.title,
+                        peers: BTreeMap::new(),
+                        unsaved: None,
+                    }),
+                    events: broadcast::channel(ROOM_BACKLOG).0,
+                    saving: Mutex::new(()),
+                });
+                tokio::spawn(autosave(site.clone(), Arc::downgrade(&room)));
+                rooms.insert(key, room.clone());
+                room
+            }
+        };
+
+        // subscribed under the lock, so nothing between `init` and the events is lost
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut state = room.state.lock().unwrap();
+        let events = room.events.subscribe();
+        let peers = state
+            .peers
+            .iter()
+            .map(|(id, (user, cursor))| PeerInfo {
+                id: *id,
+                user: user.clone(),
+                cursor: *cursor,
+            })
+            .collect();
+        state.peers.insert(id, (editor.to_string(), (0, 0)));
+        let init = ServerMessage::Init {
+            id,
+            rev: state.doc.rev(),
+            title: state.title.clone(),
+            markdown: state.doc.text(),
+            peers,
+        };
+        let _ = room.events.send(ServerMessage::Cursor {
+            id,
+            user: editor.to_string(),
+            cursor: (0, 0),
+        });
+        drop(state);
+        Ok((room.clone(), id, events, init))
+    }
+
+    /// disconnect `id`, the last one out saves and closes the room
+    fn leave(&self, site: &Site, room: &Arc<Room>, id: u64) {
+        let empty = {
+            let mut state = room.state.lock().unwrap();
+            state.peers.remove(&id);
+            let _ = room.events.send(ServerMessage::Leave { id });
+            state.peers.is_empty()
+        };
+        if !empty {
+            return;
+        }
+
+        // saved while the room is still open, so whoever joins meanwhile joins
+        // it instead of reading the page before the save lands; closed only if
+        // nobody did
+        room.save(site);
+        let mut rooms = self.rooms.lock().unwrap();
+        let key = (room.user.clone(), room.file.clone());
+        let open = rooms.get(&key).is_some_and(|open| Arc::ptr_eq(open, room));
+        if open && room.state.lock().unwrap().peers.is_empty() {
+            rooms.remove(&key);
+        }
+    }
+}
+
+impl Room {
+    /// apply a message of connection `id`, signed in as `editor`
+    fn receive(&self, id: u64, editor: &str, message: ClientMessage) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let len = state.doc.len();
+        let message = match message {
+            ClientMessage::Edit { rev, op, cursor } => {
+                let op = state.doc.edit(rev, op)?;
+                let len = state.doc.len();
+                for (peer, (_, at)) in state.peers.iter_mut() {
+                    *at = match *peer == id {
+                        true => (cursor.0.min(len), cursor.1.min(len)),
+                        false => (op.transform_index(at.0), op.transform_index(at.1)),
+                    };
+                }
+                state.unsaved = Some(editor.to_string());
+                let rev = state.doc.rev();
+                let _ = self.events.send(ServerMessage::Edit { id, rev, op });
+                ServerMessage::Cursor {
+                    id,
+                    user: editor.to_string(),
+                    cursor: state.peers[&id].1,
+                }
+            }
+            ClientMessage::Cursor { cursor } => {
+                let cursor = (cursor.0.min(len), cursor.1.min(len));
+                state.peers.entry(id).and_modify(|(_, at)| *at = cursor);
+                ServerMessage::Cursor {
+                    id,
+                    user: editor.to_string(),
+                    cursor,
+                }
+            }
+            ClientMessage::Title { title } => {
+                if !validate_title(&title) {
+                    return Err(Ex::InvalidTitle);
+                }
+                state.title = title.clone();
+                state.unsaved = Some(editor.to_string());
+                ServerMessage::Title { id, title }
+            }
+        };
+        let _ = self.events.send(message);
+        Ok(())
+    }
+
+    /// write the text back to the page, if it changed since the last save
+    ///
+    /// a failed save is kept pending and the room is told, it's tried again
+    /// with the next one
+    fn save(&self, site: &Site) {
+        let _saving = self.saving.lock().unwrap();
+        let (title, markdown, editor) = {
+            let mut state = self.state.lock().unwrap();
+            let Some(editor) = state.unsaved.take() else {
+                return;
+            };
+            (state.title.clone(), state.doc.text(), editor)
+        };
+
+        let (user, file) = (&self.user, &self.file);
+        match site
+            .store
+            .update_page(&editor, user, file, title.trim(), &markdown)
+        {
+            Ok(()) => {
+                site.cache.invalidate_page(user, file);
+                println!("Saved page: @{user}/{file} (edited together, last by {editor})");
+            }
+            Err(ex) => {
+                eprintln!("Failed to save @{user}/{file}: {ex:?}");
+                let mut state = self.state.lock().unwrap();
+                state.unsaved.get_or_insert(editor);
+                let _ = self.events.send(ServerMessage::error(ex));
+            }
+        }
+    }
+}
+
+/// save the room every `SAVE_INTERVAL`, until it closes
+///
+/// saves write to the store and wait on locks, so they run off the runtime
+async fn autosave(site: Arc<Site>, room: Weak<Room>) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let Some(room) = room.upgrade() else {
+            break;
+        };
+        let site = site.clone();
+        let _ = tokio::task::spawn_blocking(move || room.save(&site)).await;
+    }
+}
+
+/// "https://notes.example.com", from "https://notes.example.com/wiki/"
+fn origin(base_url: &str) -> String {
+    base_url.split('/').take(3).collect::<Vec<_>>().join("/")
+}
+
+/// websocket: edit a page together with everyone else editing it
+///
+/// authorized like `page_editor`, the merged markdown is saved to the page
+/// every few seconds and when the last editor leaves
+pub async fn page_collab(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, file)): Path<(String, String)>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+    // browsers send cookies along with cross-site websockets too
+    if let Some(from) = headers.get(header::ORIGIN)
+        && from.as_bytes() != origin(&site.config.base_url).as_bytes()
+    {
+        return Err(Ex::PermissionDenied);
+    }
+    site.store.check_edit(&auth_user, &user)?;
+    site.store.page(&user, &file)?.ok_or(Ex::PageNotFound)?;
+
+    Ok(upgrade
+        .max_message_size(4 * MAX_DOCUMENT_CHARS)
+        .on_upgrade(move |socket| collaborate(site, user, file, auth_user, socket)))
+}
+
+async fn collaborate(
+    site: Arc<Site>,
+    user: String,
+    file: String,
+    editor: String,
+    mut socket: WebSocket,
+) {
+    let Ok((room, id, mut events, init)) = site.collab.join(&site, &user, &file, &editor) else {
+        return;
+    };
+    println!("Joined page: @{user}/{file} ({editor})");
+
+    let mut outgoing = Some(init);
+    loop {
+        if let Some(message) = outgoing.take() {
+            let Ok(text) = serde_json::to_string(&message) else {
+                break;
+            };
+            if socket.send(Message::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let Ok(message) = serde_json::from_str(&text) else {
+                        break;
+                    };
+                    match room.receive(id, &editor, message) {
+                        Ok(()) => {}
+                        // a title that can't be saved, the editor is told
+                        Err(ex @ Ex::InvalidTitle) => outgoing = Some(ServerMessage::error(ex)),
+                        // an edit that doesn't fit, the editor reconnects and starts over
+                        Err(_) => break,
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => outgoing = event.to(id),
+                // fell behind, same as above
+                Err(_) => break,
+            },
+        }
+    }
+
+    let left = site.clone();
+    let _ = tokio::task::spawn_blocking(move || left.collab.leave(&left, &room, id)).await;
+    println!("Left page: @{user}/{file} ({editor})");
+}
@@ -0,0 +1,159 @@
+use crate::app::Site;
+use crate::handlers::cache::Freshness;
+use crate::models::pages::{Page, Sort};
+use crate::models::types::{AppState, Ex, Result};
+use askama::Template;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+
+#[derive(Clone, Copy)]
+enum Format {
+    Atom,
+    Rss,
+}
+
+struct Entry {
+    user: String,
+    title: String,
+    url: String,
+    updated: String,
+    html: String,
+}
+
+#[derive(Template)]
+#[template(path = "feeds/atom.xml")]
+struct Atom<'a> {
+    title: &'a str,
+    self_url: &'a str,
+    alternate_url: &'a str,
+    updated: &'a str,
+    entries: &'a [Entry],
+}
+
+#[derive(Template)]
+#[template(path = "feeds/rss.xml")]
+struct Rss<'a> {
+    title: &'a str,
+    self_url: &'a str,
+    alternate_url: &'a str,
+    updated: &'a str,
+    entries: &'a [Entry],
+}
+
+/// atom feed of recently changed pages
+pub async fn site_atom(State(site): AppState, headers: HeaderMap) -> Result<Response> {
+    feed(&site, None, Format::Atom, &headers)
+}
+
+/// rss feed of recently changed pages
+pub async fn site_rss(State(site): AppState, headers: HeaderMap) -> Result<Response> {
+    feed(&site, None, Format::Rss, &headers)
+}
+
+/// atom feed of a user's recently changed pages
+pub async fn user_atom(
+    State(site): AppState,
+    Path(user): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    feed(&site, Some(&user), Format::Atom, &headers)
+}
+
+/// rss feed of a user's recently changed pages
+pub async fn user_rss(
+    State(site): AppState,
+    Path(user): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    feed(&site, Some(&user), Format::Rss, &headers)
+}
+
+/// the newest `feed_limit` pages of the site, or of `user`
+fn feed(site: &Site, user: Option<&str>, format: Format, headers: &HeaderMap) -> Result<Response> {
+    let limit = site.config.feed_limit;
+    let base_url = &site.config.base_url;
+
+    let rows: Vec<(String, String, Page)> = match user {
+        None => site.store.list_pages(Sort::Date, None, limit)?,
+        Some(user) => {
+            site.store.user(user)?.ok_or(Ex::UserNotFound)?;
+            let rows = site.store.user_pages(user, limit)?;
+            rows.into_iter()
+                .map(|(file, page)| (user.to_string(), file, page))
+                .collect()
+        }
+    };
+
+    // conditional get, before rendering anything
+    let newest = rows.first().map_or(0, |(_, _, page)| page.date);
+    let mut parts: Vec<&str> = vec![env!("CARGO_PKG_VERSION"), base_url, &site.config.site_title];
+    for (user, file, page) in &rows {
+        parts.extend([user.as_str(), file, &page.title, &page.html]);
+    }
+    let freshness = Freshness::new(newest, &parts);
+    let headers_out = freshness.headers(&site.config.cache_control);
+    if freshness.is_fresh(headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers_out).into_response());
+    }
+
+    let date = |date: i64| -> Result<String> {
+        let date =
+            time::UtcDateTime::from_unix_timestamp(date).map_err(|_| Ex::InvalidTimestamp)?;
+        match format {
+            Format::Atom => date.format(&Rfc3339),
+            Format::Rss => date.format(&Rfc2822),
+        }
+        .map_err(|_| Ex::InvalidTimestamp)
+    };
+    let entries = rows
+        .into_iter()
+        .map(|(user, file, page)| {
+            Ok(Entry {
+                url: format!("{base_url}@{user}/{file}"),
+                updated: date(page.date)?,
+                user,
+                title: page.title,
+                html: page.html,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (title, alternate_url) = match user {
+        None => (site.config.site_title.clone(), base_url.clone()),
+        Some(user) => (
+            format!("@{user} | {}", site.config.site_title),
+            format!("{base_url}@{user}"),
+        ),
+    };
+    let (extension, content_type) = match format {
+        Format::Atom => ("atom", "application/atom+xml; charset=utf-8"),
+        Format::Rss => ("rss", "application/rss+xml; charset=utf-8"),
+    };
+    let self_url = match user {
+        None => format!("{base_url}feed.{extension}"),
+        Some(user) => format!("{base_url}@{user}/feed.{extension}"),
+    };
+    let updated = date(newest)?;
+
+    let body = match format {
+        Format::Atom => Atom {
+            title: &title,
+            self_url: &self_url,
+            alternate_url: &alternate_url,
+            updated: &updated,
+            entries: &entries,
+        }
+        .render()?,
+        Format::Rss => Rss {
+            title: &title,
+            self_url: &self_url,
+            alternate_url: &alternate_url,
+            updated: &updated,
+            entries: &entries,
+        }
+        .render()?,
+    };
+    Ok((headers_out, [(header::CONTENT_TYPE, content_type)], body).into_response())
+}
@@ -0,0 +1,71 @@
+use crate::models::types::AppState;
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Health {
+    status: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    status: &'static str,
+    database: Check,
+    site_root: Check,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn from_result<E: std::fmt::Display>(result: std::result::Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Check {
+                ok: true,
+                error: None,
+            },
+            Err(e) => Check {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// liveness probe (process up)
+pub async fn healthz() -> Json<Health> {
+    Json(Health {
+        status: "ok",
+        version: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// readiness probe (database and static files available)
+pub async fn readyz(State(site): AppState) -> (StatusCode, Json<Readiness>) {
+    let database = Check::from_result(site.store.ready().map_err(|e| format!("{e:?}")));
+    let site_root = Check::from_result(
+        match std::path::Path::new(&site.config.site_root).is_dir() {
+            true => Ok(()),
+            false => Err(format!("{} is not a directory", site.config.site_root)),
+        },
+    );
+
+    let ready = database.ok && site_root.ok;
+    let status_code = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    let readiness = Readiness {
+        status: if ready { "ready" } else { "unavailable" },
+        database,
+        site_root,
+    };
+    (status_code, Json(readiness))
+}
@@ -1,54 +1,127 @@
-use crate::config::CONFIG;
-use crate::models::pages::PAGES;
-use crate::models::types::{AppState, Result};
+use crate::handlers::cache::{CacheKey, Cached};
+use crate::models::pages::{Cursor, Sort};
+use crate::models::types::{AppState, Ex, Result};
 use crate::token::Token;
 use askama::Template;
-use axum::extract::{Extension, State};
-use axum::response::Html;
-use redb::{ReadableDatabase, ReadableTable};
+use axum::extract::{Extension, Query, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use serde::Deserialize;
+
+/// pages per screen of the home listing
+const PAGE_SIZE: usize = 50;
+
+#[derive(Deserialize)]
+pub struct ListQuery {
+    #[serde(default)]
+    sort: Sort,
+    // cursor of the last page on the previous screen
+    after: Option<String>,
+}
+
+/// "?sort=..&after=..", empty for the first screen by date
+fn list_query(sort: Sort, after: Option<&str>) -> String {
+    let mut query = vec![];
+    if sort != Sort::Date {
+        query.push(format!("sort={}", sort.as_str()));
+    }
+    if let Some(after) = after {
+        query.push(format!("after={after}"));
+    }
+    match query.is_empty() {
+        true => String::new(),
+        false => format!("?{}", query.join("&")),
+    }
+}
 
 /// home page & work space
 pub async fn home_page(
-    State(db): AppState,
+    State(site): AppState,
     Extension(auth): Extension<Option<String>>,
-) -> Result<Html<String>> {
+    Query(ListQuery { sort, after }): Query<ListQuery>,
+    headers: HeaderMap,
+) -> Result<Response> {
     #[derive(Template)]
     #[template(path = "home.html")]
     struct Page<'a> {
         base_url: &'a str,
         site_title: &'a str,
         // [(username, file, title, date)]
-        pages: Vec<(String, String, String)>,
+        pages: Vec<(String, String, String, String)>,
         // (username, invite_code)
         user: Option<(String, String)>,
+        // [(name, query, current)]
+        sorts: Vec<(&'a str, String, bool)>,
+        // this screen and the next one
+        query: String,
+        next_query: Option<String>,
     }
 
-    let read_txn = db.begin_read()?;
+    let key = CacheKey::Home {
+        auth: auth.clone(),
+        sort,
+        after: after.clone(),
+    };
+    if let Some(cached) = site.cache.get(&key) {
+        return Ok(cached.respond(&headers, ""));
+    }
+    let generation = site.cache.generation();
 
-    let pages = if let Ok(pages_table) = read_txn.open_table(PAGES) {
-        let i = pages_table.iter()?;
-        i.filter_map(|result| {
-            result.ok().map(|(key, value)| {
-                let (user, file) = key.value();
-                (user.into(), file.into(), value.value().title.into())
-            })
-        })
-        .collect()
-    } else {
-        vec![]
+    // one extra row tells whether there is a next screen
+    let cursor = match &after {
+        Some(after) => Some(Cursor::decode(after).ok_or(Ex::InvalidCursor)?),
+        None => None,
     };
-    // pages.sort_by(|a, b| b.3.cmp(&a.3));
+    let mut rows = site
+        .store
+        .list_pages(sort, cursor.as_ref(), PAGE_SIZE + 1)?;
+    let more = rows.len() > PAGE_SIZE;
+    rows.truncate(PAGE_SIZE);
+
+    let next_query = rows
+        .last()
+        .filter(|_| more)
+        .map(|(user, file, page)| list_query(sort, Some(&Cursor::new(user, file, page).encode())));
+    let pages = rows
+        .into_iter()
+        .map(|(user, file, page)| {
+            let date = time::UtcDateTime::from_unix_timestamp(page.date)
+                .map_err(|_| Ex::InvalidTimestamp)?
+                .format(&time::format_description::well_known::Iso8601::DATE)
+                .map_err(|_| Ex::InvalidTimestamp)?;
+            Ok((user, file, page.title, date))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let sorts = [
+        ("Last Modified", Sort::Date),
+        ("Title", Sort::Title),
+        ("Author", Sort::Author),
+    ]
+    .into_iter()
+    .map(|(name, s)| (name, list_query(s, None), s == sort))
+    .collect();
 
     let user = auth.map(|username| {
-        let t = Token::new(&username, 604800, CONFIG.secret_invite);
+        let t = Token::new(&username, 604800, &site.config.secret_invite);
         (username, t)
     });
 
     let page = Page {
-        base_url: &CONFIG.base_url,
-        site_title: &CONFIG.site_title,
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
         pages,
         user,
+        sorts,
+        query: list_query(sort, after.as_deref()),
+        next_query,
+    };
+    let cached = Cached {
+        body: page.render()?.into(),
+        freshness: None,
+        next: None,
+        series: false,
     };
-    Ok(Html(page.render()?))
+    site.cache.insert(key, cached.clone(), generation);
+    Ok(cached.respond(&headers, ""))
 }
@@ -1,20 +1,103 @@
-use crate::config::CONFIG;
+use crate::app::Site;
 use crate::handlers::auth::auth_component;
-use crate::models::pages::{PAGES, PageData};
+use crate::handlers::cache::{CacheKey, Cached, Freshness};
+use crate::models::pages::{self, PageData};
 use crate::models::types::{AppState, Ex, Result};
-use crate::models::users::USERS;
 use askama::Template;
 use axum::Json;
 use axum::extract::{Extension, Path, State};
-use axum::http::StatusCode;
-use axum::response::{Html, IntoResponse};
-use redb::{ReadableDatabase, ReadableTable};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+
+/// deepest page path, in segments
+const MAX_DEPTH: usize = 8;
+
+/// longest markdown rendered by `page_preview`, in bytes
+const MAX_PREVIEW_BYTES: usize = 256 << 10;
+
+/// longest page title, in chars
+const MAX_TITLE_CHARS: usize = 256;
+
+/// (name, path) of the folders and (path, title) of the pages right under
+/// `folder` ("" for the top)
+pub(crate) type Listing = (Vec<(String, String)>, Vec<(String, String)>);
+
+pub(crate) fn listing(site: &Site, user: &str, folder: &str) -> Result<Listing> {
+    let Some(user_data) = site.store.user(user)? else {
+        return Ok(Listing::default());
+    };
+    let (folders, files) = pages::children(&user_data.files, folder);
+    let folders = folders
+        .into_iter()
+        .map(|path| (name(&path).to_string(), path))
+        .collect();
+    let mut children = vec![];
+    for file in files {
+        if let Some(page) = site.store.page(user, file)? {
+            children.push((file.to_string(), page.title));
+        }
+    }
+    Ok((folders, children))
+}
+
+/// (name, path) of every folder above `path`
+fn breadcrumbs(path: &str) -> Vec<(&str, &str)> {
+    path.match_indices('/')
+        .map(|(i, _)| (name(&path[..i]), &path[..i]))
+        .collect()
+}
+
+/// last segment of a path
+fn name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// (user, file, title)
+type Link = (String, String, String);
+
+/// (previous, next) around `file` in `user`'s series `series`
+fn series_links(
+    site: &Site,
+    user: &str,
+    file: &str,
+    series: &str,
+) -> Result<(Option<Link>, Option<Link>)> {
+    let rows = site.store.series(user, series)?;
+    let Some(i) = rows.iter().position(|(f, _)| f == file) else {
+        return Ok((None, None));
+    };
+    let link = |i: usize| {
+        rows.get(i)
+            .map(|(file, page)| (user.to_string(), file.clone(), page.title.clone()))
+    };
+    Ok((i.checked_sub(1).and_then(link), link(i + 1)))
+}
+
+/// `/@user/path` is a page or a folder, `/@user/path/edit` its editor
+pub async fn page_route(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, path)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    match path.strip_suffix("/edit") {
+        Some(file) => {
+            let path = Path((user, file.to_string()));
+            Ok(page_editor(State(site), Extension(auth), path)
+                .await?
+                .into_response())
+        }
+        None => page_view(State(site), Extension(auth), Path((user, path)), headers).await,
+    }
+}
 
 /// page view
 pub async fn page_view(
-    State(db): AppState,
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
     Path((user, file)): Path<(String, String)>,
-) -> Result<Html<String>> {
+    headers: HeaderMap,
+) -> Result<Response> {
     #[derive(Template)]
     #[template(path = "page.html")]
     struct Page<'a> {
@@ -25,42 +108,162 @@ pub async fn page_view(
         title: &'a str,
         content: &'a str,
         // (username, file, title)
+        prev_page: Option<(&'a str, &'a str, &'a str)>,
         next_page: Option<(&'a str, &'a str, &'a str)>,
+        // (url name, name) of the series the page is in
+        series: Option<(&'a str, &'a str)>,
         date: &'a str,
+        // (name, path)
+        breadcrumbs: Vec<(&'a str, &'a str)>,
+        folders: &'a [(String, String)],
+        // (path, title)
+        children: &'a [(String, String)],
+        tags: &'a [String],
+        summary: Option<&'a str>,
     }
 
-    let read_txn = db.begin_read()?;
-    let pages_table = read_txn.open_table(PAGES)?;
+    let cache_control = match auth {
+        Some(_) => "private, no-cache",
+        None => &site.config.cache_control,
+    };
+    let key = CacheKey::Page {
+        user: user.clone(),
+        file: file.clone(),
+    };
+    if let Some(cached) = site.cache.get(&key) {
+        return Ok(cached.respond(&headers, cache_control));
+    }
+    let generation = site.cache.generation();
 
-    // get page and next page
-    let mut page_iter = pages_table.range((user.as_str(), file.as_str())..)?;
-    let current_page = page_iter.next().ok_or(Ex::PageNotFound)??.1;
-    let current_page = current_page.value();
-    let next_page = page_iter.next().transpose()?;
-    let next_page = next_page
-        .as_ref()
-        .map(|(k, v)| (k.value().0, k.value().1, v.value().title));
+    // get page (or where it moved, or the folder) and its neighbours
+    let Some(mut current_page) = site.store.page(&user, &file)? else {
+        // the page it moved to may be deleted since, then there is nothing to follow
+        if let Some((user, file)) = site.store.alias(&user, &file)?
+            && site.store.page(&user, &file)?.is_some()
+        {
+            let location = format!("{}@{user}/{file}", site.config.base_url);
+            return Ok(Redirect::permanent(&location).into_response());
+        }
+        return folder_view(&site, &user, &file);
+    };
+    let front_matter = current_page.front_matter();
+    let series = front_matter.series_key().map(|(series, _)| series);
+    let (prev_page, next_page) = match &series {
+        Some(series) => series_links(&site, &user, &file, series)?,
+        None => (None, site.store.next_page(&user, &file)?),
+    };
+    let (folders, children) = listing(&site, &user, &file)?;
+
+    // html from an older renderer is refreshed on first read
+    if current_page.is_stale() {
+        current_page = site
+            .store
+            .rerender_page(&user, &file)?
+            .ok_or(Ex::PageNotFound)?;
+    }
+
+    // conditional get, before rendering anything
+    let (prev_user, prev_file, prev_title) = prev_page.clone().unwrap_or_default();
+    let (next_user, next_file, next_title) = next_page.clone().unwrap_or_default();
+    let mut parts: Vec<&str> = vec![
+        env!("CARGO_PKG_VERSION"),
+        &site.config.base_url,
+        &site.config.site_title,
+        &current_page.title,
+        &current_page.html,
+        &prev_user,
+        &prev_file,
+        &prev_title,
+        &next_user,
+        &next_file,
+        &next_title,
+        series.as_deref().unwrap_or_default(),
+        front_matter.series.as_deref().unwrap_or_default(),
+        front_matter.summary.as_deref().unwrap_or_default(),
+    ];
+    parts.extend(front_matter.tags.iter().map(String::as_str));
+    parts.extend(folders.iter().map(|(_, path)| path.as_str()));
+    for (path, title) in &children {
+        parts.extend([path.as_str(), title]);
+    }
+    let freshness = Freshness::new(current_page.date, &parts);
+    if freshness.is_fresh(&headers) {
+        let headers_out = freshness.headers(cache_control);
+        return Ok((StatusCode::NOT_MODIFIED, headers_out).into_response());
+    }
 
     // render
     let page = Page {
-        base_url: CONFIG.base_url,
-        site_title: CONFIG.site_title,
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
         username: &user,
         file: &file,
-        title: current_page.title,
-        content: current_page.html,
-        next_page: next_page,
+        title: &current_page.title,
+        content: &current_page.html,
+        prev_page: prev_page
+            .as_ref()
+            .map(|(user, file, title)| (user.as_str(), file.as_str(), title.as_str())),
+        next_page: next_page
+            .as_ref()
+            .map(|(user, file, title)| (user.as_str(), file.as_str(), title.as_str())),
+        series: series.as_deref().zip(front_matter.series.as_deref()),
         date: &time::UtcDateTime::from_unix_timestamp(current_page.date)
             .map_err(|_| Ex::InvalidTimestamp)?
             .format(&time::format_description::well_known::Iso8601::DATE)
             .map_err(|_| Ex::InvalidTimestamp)?,
+        breadcrumbs: breadcrumbs(&file),
+        folders: &folders,
+        children: &children,
+        tags: &front_matter.tags,
+        summary: front_matter.summary.as_deref(),
+    };
+    let cached = Cached {
+        body: page.render()?.into(),
+        freshness: Some(freshness),
+        next: next_page.map(|(user, file, _)| (user, file)),
+        series: series.is_some(),
+    };
+    site.cache.insert(key, cached.clone(), generation);
+    Ok(cached.respond(&headers, cache_control))
+}
+
+/// folder index, the pages under `folder`
+fn folder_view(site: &Site, user: &str, folder: &str) -> Result<Response> {
+    #[derive(Template)]
+    #[template(path = "folder.html")]
+    struct Page<'a> {
+        base_url: &'a str,
+        site_title: &'a str,
+        username: &'a str,
+        folder: &'a str,
+        name: &'a str,
+        // (name, path)
+        breadcrumbs: Vec<(&'a str, &'a str)>,
+        folders: &'a [(String, String)],
+        // (path, title)
+        children: &'a [(String, String)],
+    }
+
+    let (folders, children) = listing(site, user, folder)?;
+    if folders.is_empty() && children.is_empty() {
+        return Err(Ex::PageNotFound);
+    }
+    let page = Page {
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
+        username: user,
+        folder,
+        name: name(folder),
+        breadcrumbs: breadcrumbs(folder),
+        folders: &folders,
+        children: &children,
     };
-    Ok(Html(page.render()?))
+    Ok(Html(page.render()?).into_response())
 }
 
 /// page editor
 pub async fn page_editor(
-    State(db): AppState,
+    State(site): AppState,
     Extension(auth): Extension<Option<String>>,
     Path((user, file)): Path<(String, String)>,
 ) -> Result<impl IntoResponse> {
@@ -73,47 +276,77 @@ pub async fn page_editor(
         file: &'a str,
         title: &'a str,
         markdown: &'a str,
+        // (name, mime, size)
+        attachments: Vec<(String, String, usize)>,
+        // (title, markdown) of the signed-in user's unpublished edit
+        draft: Option<(String, String)>,
+        draft_saved: String,
     }
 
     // auth page
     let Some(auth_user) = auth else {
-        let url = format!("{}@{user}/{file}", CONFIG.base_url);
-        return Ok((StatusCode::FORBIDDEN, auth_component(None, &url)?).into_response());
+        let url = format!("{}@{user}/{file}", site.config.base_url);
+        return Ok((
+            StatusCode::FORBIDDEN,
+            auth_component(&site.config, None, &url)?,
+        )
+            .into_response());
     };
 
-    let read_txn = db.begin_read()?;
-    let users_table = read_txn.open_table(USERS)?;
-    let pages_table = read_txn.open_table(PAGES)?;
-
     // check permissions
-    if auth_user != user
-        && let Some(user) = users_table.get(user.as_str())?
-        && !user.value().collabs.contains(&auth_user)
-    {
-        return Err(Ex::PermissionDenied);
-    }
+    site.store.check_edit(&auth_user, &user)?;
 
-    // target page
-    let page = pages_table
-        .get(&(user.as_str(), file.as_str()))?
-        .ok_or(Ex::PageNotFound)?;
-    let page = page.value();
+    // target page, and where this editor left off
+    let page = site.store.page(&user, &file)?.ok_or(Ex::PageNotFound)?;
+    let draft = site.store.draft(&user, &file, &auth_user)?;
+    let draft_saved = match &draft {
+        Some(draft) => time::UtcDateTime::from_unix_timestamp(draft.saved_at)
+            .map_err(|_| Ex::InvalidTimestamp)?
+            .format(&time::format_description::well_known::Rfc3339)
+            .map_err(|_| Ex::InvalidTimestamp)?,
+        None => String::new(),
+    };
 
     // render
     let page = Page {
-        base_url: CONFIG.base_url,
-        site_title: CONFIG.site_title,
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
         username: &user,
         file: &file,
-        title: page.title,
-        markdown: page.markdown,
+        title: &page.title,
+        markdown: &page.markdown,
+        attachments: site.store.attachments(&user, &file)?,
+        draft: draft.map(|draft| (draft.title, draft.markdown)),
+        draft_saved,
     };
     Ok(Html(page.render()?).into_response())
 }
 
 /// api: update page
 pub async fn page_update(
-    State(db): AppState,
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, file)): Path<(String, String)>,
+    Json((title, markdown)): Json<(String, String)>,
+) -> Result<()> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+    if !validate_title(&title) {
+        return Err(Ex::InvalidTitle);
+    }
+
+    site.store
+        .update_page(&auth_user, &user, &file, &title, &markdown)?;
+    site.cache.invalidate_page(&user, &file);
+    println!("Updated page: @{}/{}", user, file);
+    Ok(())
+}
+
+/// api: autosave an edit as the signed-in user's draft, the page stays as is
+pub async fn draft_save(
+    State(site): AppState,
     Extension(auth): Extension<Option<String>>,
     Path((user, file)): Path<(String, String)>,
     Json((title, markdown)): Json<(String, String)>,
@@ -123,93 +356,93 @@ pub async fn page_update(
         return Err(Ex::PermissionDenied);
     };
 
-    let write_txn = db.begin_write()?;
-    {
-        let mut users_table = write_txn.open_table(USERS)?;
-        let mut pages_table = write_txn.open_table(PAGES)?;
+    site.store
+        .save_draft(&auth_user, &user, &file, &title, &markdown)?;
+    Ok(())
+}
 
-        // target user
-        let mut target_entry = users_table
-            .get_mut(user.as_str())?
-            .ok_or(Ex::UserNotFound)?;
-        let mut target_data = target_entry.value().clone();
+/// api: drop the signed-in user's draft
+pub async fn draft_discard(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, file)): Path<(String, String)>,
+) -> Result<()> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
 
-        // check permissions
-        if auth_user != user && !target_data.collabs.contains(&auth_user) {
-            return Err(Ex::PermissionDenied);
-        }
+    site.store.discard_draft(&auth_user, &user, &file)?;
+    Ok(())
+}
 
-        // update file
-        let mut page_entry = pages_table
-            .get_mut(&(user.as_str(), file.as_str()))?
-            .ok_or(Ex::PageNotFound)?;
-        page_entry.insert(PageData::new(&title, &markdown, &mut String::new()))?;
-        target_data.files.insert(file.clone());
-        target_entry.insert(target_data)?;
+/// api: render markdown like a saved page would be, without saving it
+pub async fn page_preview(
+    Extension(auth): Extension<Option<String>>,
+    Json((markdown,)): Json<(String,)>,
+) -> Result<Html<String>> {
+    // check
+    if auth.is_none() {
+        return Err(Ex::PermissionDenied);
     }
-    write_txn.commit()?;
-    println!("Updated page: @{}/{}", user, file);
-    Ok(())
+    if markdown.len() > MAX_PREVIEW_BYTES {
+        return Err(Ex::ContentTooLarge);
+    }
+
+    let mut html = String::new();
+    PageData::render(&markdown, &mut html);
+    Ok(Html(html))
 }
 
 /// api: create page
 pub async fn page_create(
-    State(db): AppState,
+    State(site): AppState,
     Extension(auth): Extension<Option<String>>,
     Path((user, file)): Path<(String, String)>,
 ) -> Result<()> {
-    #[inline]
-    fn validate_name(n: &str) -> bool {
-        n.chars()
-            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
-            && (1..=120).contains(&n.len())
-    }
-
     // check
     let Some(auth_user) = auth else {
         return Err(Ex::PermissionDenied);
     };
-    if !validate_name(&file) {
+    if !validate_path(&file) {
         return Err(Ex::InvalidFilename);
     }
 
-    let write_txn = db.begin_write()?;
-    {
-        let mut users_table = write_txn.open_table(USERS)?;
-        let mut pages_table = write_txn.open_table(PAGES)?;
-
-        // target user
-        let mut target_entry = users_table
-            .get_mut(user.as_str())?
-            .ok_or(Ex::UserNotFound)?;
-        let mut target_data = target_entry.value().clone();
-
-        // check permissions
-        if auth_user != user && !target_data.collabs.contains(&auth_user) {
-            return Err(Ex::PermissionDenied);
-        }
+    site.store.create_page(&auth_user, &user, &file)?;
+    site.cache.invalidate_page(&user, &file);
+    println!("Created page: @{}/{}", user, file);
+    Ok(())
+}
 
-        // check if page already exists
-        if pages_table.get(&(user.as_str(), file.as_str()))?.is_some() {
-            return Err(Ex::PageAlreadyExists);
-        }
+/// api: rename page, or move it to a collaborator's namespace
+pub async fn page_rename(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, file)): Path<(String, String)>,
+    Json((to_user, to_file)): Json<(String, String)>,
+) -> Result<()> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+    if !validate_path(&to_file) {
+        return Err(Ex::InvalidFilename);
+    }
 
-        // create new page
-        target_data.files.insert(file.clone());
-        target_entry.insert(target_data)?;
-        pages_table.insert(
-            &(user.as_str(), file.as_str()),
-            PageData::new("Untitled", "", &mut String::new()),
-        )?;
+    let moves = site
+        .store
+        .rename_page(&auth_user, &user, &file, &to_user, &to_file)?;
+    for (from, to) in &moves {
+        site.cache.invalidate_page(&user, from);
+        site.cache.invalidate_page(&to_user, to);
     }
-    write_txn.commit()?;
-    println!("Created page: @{}/{}", user, file);
+    println!("Renamed page: @{user}/{file} -> @{to_user}/{to_file}");
     Ok(())
 }
 
 /// api: delete page
 pub async fn page_delete(
-    State(db): AppState,
+    State(site): AppState,
     Extension(auth): Extension<Option<String>>,
     Path((user, file)): Path<(String, String)>,
 ) -> Result<()> {
@@ -218,28 +451,28 @@ pub async fn page_delete(
         return Err(Ex::PermissionDenied);
     };
 
-    let write_txn = db.begin_write()?;
-    {
-        let mut users_table = write_txn.open_table(USERS)?;
-        let mut pages_table = write_txn.open_table(PAGES)?;
-
-        // target user
-        let mut target_entry = users_table
-            .get_mut(user.as_str())?
-            .ok_or(Ex::UserNotFound)?;
-        let mut target_data = target_entry.value().clone();
+    site.store.delete_page(&auth_user, &user, &file)?;
+    site.cache.invalidate_page(&user, &file);
+    println!("Deleted page: @{}/{}", user, file);
+    Ok(())
+}
 
-        // check permissions
-        if auth_user != user && !target_data.collabs.contains(&auth_user) {
-            return Err(Ex::PermissionDenied);
-        }
+/// anything but blank, and not too long
+pub(super) fn validate_title(title: &str) -> bool {
+    !title.trim().is_empty() && title.chars().count() <= MAX_TITLE_CHARS
+}
 
-        // remove
-        target_data.files.remove(&file);
-        target_entry.insert(target_data)?;
-        pages_table.remove(&(user.as_str(), file.as_str()))?;
+/// "notes" or "projects/alpha/notes", "edit" only at the top (it's the editor)
+fn validate_path(path: &str) -> bool {
+    #[inline]
+    fn validate_name(n: &str) -> bool {
+        n.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'))
+            && (1..=120).contains(&n.len())
     }
-    write_txn.commit()?;
-    println!("Deleted page: @{}/{}", user, file);
-    Ok(())
+
+    let segments: Vec<&str> = path.split('/').collect();
+    segments.len() <= MAX_DEPTH
+        && segments.iter().all(|s| validate_name(s))
+        && !segments[1..].contains(&"edit")
 }
@@ -0,0 +1,44 @@
+use crate::models::front_matter;
+use crate::models::types::{AppState, Ex, Result};
+use askama::Template;
+use axum::extract::{Path, State};
+use axum::response::Html;
+
+/// table of contents of a series, in reading order
+pub async fn series_page(
+    State(site): AppState,
+    Path((user, series)): Path<(String, String)>,
+) -> Result<Html<String>> {
+    #[derive(Template)]
+    #[template(path = "series.html")]
+    struct Page<'a> {
+        base_url: &'a str,
+        site_title: &'a str,
+        username: &'a str,
+        series: &'a str,
+        // as written in the front matter of the first page
+        name: &'a str,
+        // (file, title)
+        pages: Vec<(String, String)>,
+    }
+
+    let series = front_matter::normalize_tag(&series).ok_or(Ex::PageNotFound)?;
+    let rows = site.store.series(&user, &series)?;
+    let Some((_, first)) = rows.first() else {
+        return Err(Ex::PageNotFound);
+    };
+    let name = first.front_matter().series.unwrap_or_default();
+
+    let page = Page {
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
+        username: &user,
+        series: &series,
+        name: &name,
+        pages: rows
+            .into_iter()
+            .map(|(file, page)| (file, page.title))
+            .collect(),
+    };
+    Ok(Html(page.render()?))
+}
@@ -0,0 +1,110 @@
+use crate::config::Config;
+use crate::handlers::cache::Freshness;
+use crate::models::pages::Sort;
+use crate::models::types::{AppState, Ex, Result};
+use askama::Template;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use time::format_description::well_known::Rfc3339;
+
+/// most urls a single sitemap may list
+const SITEMAP_LIMIT: usize = 50_000;
+
+/// sitemap of every page, with its last modification
+pub async fn sitemap(State(site): AppState, headers: HeaderMap) -> Result<Response> {
+    #[derive(Template)]
+    #[template(path = "sitemap.xml")]
+    struct Sitemap<'a> {
+        base_url: &'a str,
+        // [(url, lastmod)]
+        urls: Vec<(String, String)>,
+    }
+
+    let base_url = &site.config.base_url;
+    let rows = site.store.list_pages(Sort::Date, None, SITEMAP_LIMIT)?;
+
+    // conditional get, before rendering anything
+    let newest = rows.first().map_or(0, |(_, _, page)| page.date);
+    let keys: Vec<String> = rows.iter().map(|(u, f, _)| format!("{u}/{f}")).collect();
+    let mut parts: Vec<&str> = vec![env!("CARGO_PKG_VERSION"), base_url];
+    parts.extend(keys.iter().map(String::as_str));
+    let freshness = Freshness::new(newest, &parts);
+    let headers_out = freshness.headers(&site.config.cache_control);
+    if freshness.is_fresh(&headers) {
+        return Ok((StatusCode::NOT_MODIFIED, headers_out).into_response());
+    }
+
+    let urls = rows
+        .into_iter()
+        .map(|(user, file, page)| {
+            let lastmod = time::UtcDateTime::from_unix_timestamp(page.date)
+                .map_err(|_| Ex::InvalidTimestamp)?
+                .format(&Rfc3339)
+                .map_err(|_| Ex::InvalidTimestamp)?;
+            Ok((format!("{base_url}@{user}/{file}"), lastmod))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let body = Sitemap { base_url, urls }.render()?;
+    let content_type = [(header::CONTENT_TYPE, "application/xml; charset=utf-8")];
+    Ok((headers_out, content_type, body).into_response())
+}
+
+/// paths crawlers are kept out of: editors, sign-in, invites and the api
+const DISALLOWED: &[&str] = &[
+    "@*/*/edit$",
+    "auth",
+    "invite/",
+    "page/",
+    "rename/",
+    "trash/",
+    "image/",
+    "preview",
+    "draft/",
+    "collab/",
+    "admin/",
+];
+
+/// crawl pages, but not editors, sign-in, invites or the api
+pub async fn robots(State(site): AppState) -> Response {
+    robots_txt(&[&site.config])
+}
+
+/// robots.txt of the sites on one host
+///
+/// crawlers only ask the root of a host, so `sites` answers it there for every
+/// site on the host, prefixed ones included
+pub fn robots_txt(configs: &[&Config]) -> Response {
+    let mut robots = String::from("User-agent: *\n");
+    for config in configs {
+        // path of `base_url`, "/" unless the site is mounted under a prefix
+        let path = config
+            .base_url
+            .split_once("://")
+            .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+            .unwrap_or("/");
+        for rule in DISALLOWED {
+            robots.push_str(&format!("Disallow: {path}{rule}\n"));
+        }
+    }
+    robots.push('\n');
+    for config in configs {
+        robots.push_str(&format!("Sitemap: {}sitemap.xml\n", config.base_url));
+    }
+
+    let cache_control = configs
+        .first()
+        .map_or_else(String::new, |config| config.cache_control.clone());
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/plain; charset=utf-8".to_string(),
+            ),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        robots,
+    )
+        .into_response()
+}
@@ -0,0 +1,62 @@
+use crate::models::front_matter;
+use crate::models::types::{AppState, Ex, Result};
+use askama::Template;
+use axum::extract::{Path, State};
+use axum::response::Html;
+
+/// every tag, with the number of pages
+pub async fn tags_page(State(site): AppState) -> Result<Html<String>> {
+    #[derive(Template)]
+    #[template(path = "tags.html")]
+    struct Page<'a> {
+        base_url: &'a str,
+        site_title: &'a str,
+        // (tag, count)
+        tags: Vec<(String, usize)>,
+    }
+
+    let page = Page {
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
+        tags: site.store.tags()?,
+    };
+    Ok(Html(page.render()?))
+}
+
+/// pages with a tag, newest first
+pub async fn tag_page(State(site): AppState, Path(tag): Path<String>) -> Result<Html<String>> {
+    #[derive(Template)]
+    #[template(path = "tag.html")]
+    struct Page<'a> {
+        base_url: &'a str,
+        site_title: &'a str,
+        tag: &'a str,
+        // (username, file, title, date)
+        pages: Vec<(String, String, String, String)>,
+    }
+
+    let tag = front_matter::normalize_tag(&tag).ok_or(Ex::PageNotFound)?;
+    let pages = site
+        .store
+        .tagged(&tag)?
+        .into_iter()
+        .map(|(user, file, page)| {
+            let date = time::UtcDateTime::from_unix_timestamp(page.date)
+                .map_err(|_| Ex::InvalidTimestamp)?
+                .format(&time::format_description::well_known::Iso8601::DATE)
+                .map_err(|_| Ex::InvalidTimestamp)?;
+            Ok((user, file, page.title, date))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if pages.is_empty() {
+        return Err(Ex::PageNotFound);
+    }
+
+    let page = Page {
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
+        tag: &tag,
+        pages,
+    };
+    Ok(Html(page.render()?))
+}
@@ -0,0 +1,98 @@
+use crate::handlers::auth::auth_component;
+use crate::models::types::{AppState, Ex, Result};
+use askama::Template;
+use axum::extract::{Extension, Path, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+
+/// deleted pages of a user, for anyone who may edit them
+pub async fn trash_view(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path(user): Path<String>,
+) -> Result<Response> {
+    #[derive(Template)]
+    #[template(path = "trash.html")]
+    struct Page<'a> {
+        base_url: &'a str,
+        site_title: &'a str,
+        username: &'a str,
+        trash_days: u64,
+        // (file, title, deleted_by, date, deleted_at)
+        pages: Vec<(&'a str, &'a str, &'a str, String, i64)>,
+    }
+
+    // auth page
+    let Some(auth_user) = auth else {
+        let url = format!("{}trash/{user}", site.config.base_url);
+        return Ok((
+            StatusCode::FORBIDDEN,
+            auth_component(&site.config, None, &url)?,
+        )
+            .into_response());
+    };
+
+    // check permissions
+    site.store.check_edit(&auth_user, &user)?;
+
+    // render
+    let trash = site.store.trash(&user)?;
+    let pages = trash
+        .iter()
+        .map(|t| {
+            let date = time::UtcDateTime::from_unix_timestamp(t.deleted_at)
+                .map_err(|_| Ex::InvalidTimestamp)?
+                .format(&time::format_description::well_known::Iso8601::DATE)
+                .map_err(|_| Ex::InvalidTimestamp)?;
+            let (file, title, by) = (
+                t.file.as_str(),
+                t.page.title.as_str(),
+                t.deleted_by.as_str(),
+            );
+            Ok((file, title, by, date, t.deleted_at))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let page = Page {
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
+        username: &user,
+        trash_days: site.config.trash_days,
+        pages,
+    };
+    Ok(Html(page.render()?).into_response())
+}
+
+/// api: restore a deleted page
+pub async fn trash_restore(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, deleted_at, file)): Path<(String, i64, String)>,
+) -> Result<()> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+
+    site.store
+        .restore_page(&auth_user, &user, &file, deleted_at)?;
+    site.cache.invalidate_page(&user, &file);
+    println!("Restored page: @{}/{}", user, file);
+    Ok(())
+}
+
+/// api: purge a deleted page
+pub async fn trash_purge(
+    State(site): AppState,
+    Extension(auth): Extension<Option<String>>,
+    Path((user, deleted_at, file)): Path<(String, i64, String)>,
+) -> Result<()> {
+    // check
+    let Some(auth_user) = auth else {
+        return Err(Ex::PermissionDenied);
+    };
+
+    site.store
+        .purge_page(&auth_user, &user, &file, deleted_at)?;
+    println!("Purged page: @{}/{}", user, file);
+    Ok(())
+}
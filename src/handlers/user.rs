@@ -1,14 +1,11 @@
-use crate::config::CONFIG;
-use crate::models::pages::PAGES;
+use crate::handlers::page::listing;
 use crate::models::types::{AppState, Ex, Result};
-use crate::models::users::USERS;
 use askama::Template;
 use axum::extract::{Path, State};
 use axum::response::Html;
-use redb::ReadableDatabase;
 
 /// user page
-pub async fn user_page(State(db): AppState, Path(user): Path<String>) -> Result<Html<String>> {
+pub async fn user_page(State(site): AppState, Path(user): Path<String>) -> Result<Html<String>> {
     #[derive(Template)]
     #[template(path = "user.html")]
     struct Page<'a> {
@@ -17,40 +14,29 @@ pub async fn user_page(State(db): AppState, Path(user): Path<String>) -> Result<
         username: &'a str,
         // [username]
         collabs: Vec<&'a String>,
-        // (file, title)
-        pages: Vec<(&'a str, &'a str)>,
+        // (name, path) of top-level folders
+        folders: &'a [(String, String)],
+        // (file, title) of top-level pages
+        children: &'a [(String, String)],
     }
 
-    let read_txn = db.begin_read()?;
-    let users_table = read_txn.open_table(USERS)?;
-    let pages_table = read_txn.open_table(PAGES).ok();
-    let pages_table_ref = pages_table.as_ref();
-
     // user data
-    let user_guard = users_table.get(user.as_str())?.ok_or(Ex::UserNotFound)?;
-    let user_data = user_guard.value();
+    let user_data = site.store.user(&user)?.ok_or(Ex::UserNotFound)?;
 
     // collabs list
     let collabs: Vec<&String> = user_data.collabs.iter().collect();
 
     // pages list
-    let pages_guards: Vec<(&String, _)> = user_data
-        .files
-        .iter()
-        .filter_map(|f| Some((f, pages_table_ref?.get(&(user.as_str(), f.as_str())).ok()??)))
-        .collect();
-    let pages: Vec<(&str, &str)> = pages_guards
-        .iter()
-        .map(|(file, guard)| (file.as_str(), guard.value().title))
-        .collect();
+    let (folders, children) = listing(&site, &user, "")?;
 
     // render
     let page = Page {
-        base_url: &CONFIG.base_url,
-        site_title: &CONFIG.site_title,
+        base_url: &site.config.base_url,
+        site_title: &site.config.site_title,
         username: &user,
         collabs,
-        pages,
+        folders: &folders,
+        children: &children,
     };
     Ok(Html(page.render()?))
 }
@@ -1,59 +1,338 @@
+pub mod app;
+pub use app::{Site, app, router, sites};
+
 pub mod models {
+    pub mod attachments;
+    pub mod backup;
+    pub mod collab;
+    pub mod drafts;
+    pub mod front_matter;
+    pub mod fsck;
+    pub mod images;
+    pub mod memory_store;
     pub mod pages;
+    pub mod redb_store;
+    pub mod schema;
+    pub mod store;
+    pub mod trash;
     pub mod types;
     pub mod users;
 }
 
 pub mod handlers {
+    mod admin;
+    mod attachments;
     mod auth;
+    mod cache;
+    mod collab;
+    mod feed;
+    mod health;
     mod home;
     mod page;
+    mod series;
+    mod sitemap;
+    mod tags;
+    mod trash;
     mod user;
+    pub use admin::*;
+    pub use attachments::*;
     pub use auth::*;
+    pub use cache::*;
+    pub use collab::*;
+    pub use feed::*;
+    pub use health::*;
     pub use home::*;
     pub use page::*;
+    pub use series::*;
+    pub use sitemap::*;
+    pub use tags::*;
+    pub use trash::*;
     pub use user::*;
 }
 
 pub mod config {
     use serde::Deserialize;
-    use std::sync::LazyLock;
 
-    #[derive(Deserialize)]
+    #[derive(Debug, Clone, Deserialize)]
     pub struct Config {
-        pub server_addr: &'static str,
-        pub database_path: &'static str,
-        pub site_root: &'static str,
-        pub base_url: &'static str,
-        pub cookie_path: &'static str,
-        pub site_title: &'static str,
-        pub secret_invite: &'static str,
-        pub secret_passwd: &'static str,
-    }
-
-    pub static CONFIG: LazyLock<Config> = LazyLock::new(|| {
-        let content = std::fs::read_to_string("server.toml").unwrap();
-        let value: toml::Value = toml::from_str(&content).unwrap();
-        let get = |key: &str| -> &'static str {
-            Box::leak(value[key].as_str().unwrap().to_owned().into_boxed_str())
+        pub server_addr: String,
+        pub database_path: String,
+        pub site_root: String,
+        pub base_url: String,
+        pub cookie_path: String,
+        pub site_title: String,
+        pub secret_invite: String,
+        pub secret_passwd: String,
+        // users allowed to use /admin endpoints
+        #[serde(default)]
+        pub admins: Vec<String>,
+        // backups (interval in seconds, 0 disables scheduled backups)
+        #[serde(default = "default_backup_dir")]
+        pub backup_dir: String,
+        #[serde(default)]
+        pub backup_interval: u64,
+        #[serde(default = "default_backup_retention")]
+        pub backup_retention: usize,
+        // Cache-Control of pages for anonymous readers (signed-in users get "private")
+        #[serde(default = "default_cache_control")]
+        pub cache_control: String,
+        // rendered-page cache limits (0 entries disables it)
+        #[serde(default = "default_page_cache_entries")]
+        pub page_cache_entries: usize,
+        #[serde(default = "default_page_cache_bytes")]
+        pub page_cache_bytes: usize,
+        // entries in atom/rss feeds
+        #[serde(default = "default_feed_limit")]
+        pub feed_limit: usize,
+        // largest attachment accepted, in bytes
+        #[serde(default = "default_max_attachment_bytes")]
+        pub max_attachment_bytes: usize,
+        // deleted pages are purged after this many days (0 keeps them)
+        #[serde(default = "default_trash_days")]
+        pub trash_days: u64,
+        // multi-site routing, by `Host` header and/or path prefix (like "/ops")
+        #[serde(default)]
+        pub host: Option<String>,
+        #[serde(default)]
+        pub prefix: Option<String>,
+    }
+
+    fn default_backup_dir() -> String {
+        "backups".to_string()
+    }
+
+    fn default_backup_retention() -> usize {
+        7
+    }
+
+    fn default_cache_control() -> String {
+        "public, no-cache".to_string()
+    }
+
+    fn default_page_cache_entries() -> usize {
+        1024
+    }
+
+    fn default_page_cache_bytes() -> usize {
+        64 << 20
+    }
+
+    fn default_feed_limit() -> usize {
+        20
+    }
+
+    fn default_max_attachment_bytes() -> usize {
+        10 << 20
+    }
+
+    fn default_trash_days() -> u64 {
+        30
+    }
+
+    impl Config {
+        /// read a `server.toml`
+        pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+            let content = std::fs::read_to_string(path)?;
+            Ok(toml::from_str(&content)?)
+        }
+
+        pub fn is_admin(&self, user: &str) -> bool {
+            self.admins.iter().any(|admin| admin == user)
+        }
+
+        /// "host/prefix", names the site in logs and on the command line
+        pub fn site_key(&self) -> String {
+            let host = self.host.as_deref().unwrap_or_default();
+            let prefix = self.prefix.as_deref().unwrap_or_default();
+            match format!("{host}{prefix}") {
+                key if key.is_empty() => "/".to_string(),
+                key => key,
+            }
+        }
+
+        /// "token", or "token-wiki" for a site under "/wiki", so the sessions of
+        /// sites nested on one host (like "/" and "/wiki") don't shadow each other
+        pub fn cookie_name(&self) -> String {
+            let Some(prefix) = &self.prefix else {
+                return "token".to_string();
+            };
+            let name: String = prefix
+                .trim_start_matches('/')
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+                    true => c,
+                    false => '_',
+                })
+                .collect();
+            format!("token-{name}")
+        }
+    }
+
+    /// read a `server.toml` with one or more sites
+    pub fn load_sites(path: &str) -> Result<Vec<Config>, Box<dyn std::error::Error>> {
+        parse_sites(&std::fs::read_to_string(path)?)
+    }
+
+    /// a plain config is a single site, `[[sites]]` tables are one site each
+    ///
+    /// keys missing from a site table are taken from the top level, so shared
+    /// settings like `server_addr` only need to be written once
+    pub fn parse_sites(content: &str) -> Result<Vec<Config>, Box<dyn std::error::Error>> {
+        let mut shared: toml::Table = toml::from_str(content)?;
+        let sites = match shared.remove("sites") {
+            None => vec![toml::Value::Table(toml::Table::new())],
+            Some(toml::Value::Array(sites)) => sites,
+            Some(_) => return Err("`sites` must be an array of tables".into()),
         };
 
-        Config {
-            server_addr: get("server_addr"),
-            database_path: get("database_path"),
-            site_root: get("site_root"),
-            base_url: get("base_url"),
-            cookie_path: get("cookie_path"),
-            site_title: get("site_title"),
-            secret_invite: get("secret_invite"),
-            secret_passwd: get("secret_passwd"),
+        let mut configs = Vec::new();
+        for site in sites {
+            let toml::Value::Table(site) = site else {
+                return Err("`sites` must be an array of tables".into());
+            };
+            let mut table = shared.clone();
+            table.extend(site);
+            configs.push(table.try_into()?);
+        }
+        validate_sites(&configs)?;
+        Ok(configs)
+    }
+
+    /// sites must not share routes, databases, invites or cookies
+    fn validate_sites(configs: &[Config]) -> Result<(), Box<dyn std::error::Error>> {
+        if configs.is_empty() {
+            return Err("`sites` is empty".into());
+        }
+        for (i, config) in configs.iter().enumerate() {
+            let key = config.site_key();
+            if let Some(prefix) = &config.prefix {
+                let valid = prefix.starts_with('/') && prefix.len() > 1 && !prefix.ends_with('/');
+                if !valid {
+                    return Err(format!("{key}: prefix must look like \"/name\"").into());
+                }
+                if !config.base_url.ends_with(&format!("{prefix}/")) {
+                    return Err(format!("{key}: base_url must end with \"{prefix}/\"").into());
+                }
+            }
+
+            for other in &configs[..i] {
+                if config.server_addr != other.server_addr {
+                    return Err("all sites must share one server_addr".into());
+                }
+                let clash = if config.host != other.host {
+                    None
+                } else if config.prefix == other.prefix {
+                    Some("host and prefix")
+                } else if config.cookie_path == other.cookie_path {
+                    Some("cookie_path on the same host")
+                } else if config.cookie_name() == other.cookie_name() {
+                    Some("cookie name on the same host")
+                } else {
+                    None
+                };
+                let clash = clash
+                    .or((config.database_path == other.database_path).then_some("database_path"))
+                    .or((config.secret_invite == other.secret_invite).then_some("secret_invite"));
+                if let Some(what) = clash {
+                    let other = other.site_key();
+                    return Err(format!("sites {other} and {key} share their {what}").into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SHARED: &str = r#"
+            server_addr = "127.0.0.1:8080"
+            site_root = "site"
+            cookie_path = "/"
+            secret_passwd = "passwd"
+        "#;
+
+        fn site(host: &str, prefix: &str, db: &str, invite: &str) -> String {
+            let base_url = format!("http://{host}{prefix}/");
+            let cookie_path = format!("{prefix}/");
+            format!(
+                "[[sites]]\nhost = \"{host}\"\nbase_url = \"{base_url}\"\n\
+                 cookie_path = \"{cookie_path}\"\nsite_title = \"{host}\"\n\
+                 database_path = \"{db}\"\nsecret_invite = \"{invite}\"\n{}",
+                match prefix {
+                    "" => String::new(),
+                    prefix => format!("prefix = \"{prefix}\"\n"),
+                }
+            )
         }
-    });
+
+        #[test]
+        fn sites_inherit_shared_keys() {
+            let content = [
+                SHARED,
+                &site("a.test", "", "a.redb", "a"),
+                &site("b.test", "/wiki", "b.redb", "b"),
+            ]
+            .concat();
+            let sites = parse_sites(&content).unwrap();
+            assert_eq!(sites.len(), 2);
+            assert_eq!(sites[1].server_addr, "127.0.0.1:8080");
+            assert_eq!(sites[1].cookie_path, "/wiki/");
+            assert_eq!(sites[1].site_key(), "b.test/wiki");
+        }
+
+        #[test]
+        fn nested_sites_have_their_own_cookies() {
+            let content = [
+                SHARED,
+                &site("a.test", "", "a.redb", "a"),
+                &site("a.test", "/wiki", "b.redb", "b"),
+            ]
+            .concat();
+            let sites = parse_sites(&content).unwrap();
+            assert_eq!(sites[0].cookie_name(), "token");
+            assert_eq!(sites[1].cookie_name(), "token-wiki");
+
+            // "/a/b" and "/a_b" would both be "token-a_b"
+            let content = [
+                SHARED,
+                &site("a.test", "/a/b", "a.redb", "a"),
+                &site("a.test", "/a_b", "b.redb", "b"),
+            ]
+            .concat();
+            assert!(parse_sites(&content).is_err());
+        }
+
+        #[test]
+        fn sites_must_be_isolated() {
+            let clashes = [
+                // same route
+                [
+                    site("a.test", "", "a.redb", "a"),
+                    site("a.test", "", "b.redb", "b"),
+                ],
+                // same database
+                [
+                    site("a.test", "", "a.redb", "a"),
+                    site("b.test", "", "a.redb", "b"),
+                ],
+                // same invite secret
+                [
+                    site("a.test", "", "a.redb", "a"),
+                    site("b.test", "", "b.redb", "a"),
+                ],
+            ];
+            for sites in clashes {
+                let content = [SHARED, &sites[0], &sites[1]].concat();
+                assert!(parse_sites(&content).is_err(), "{content}");
+            }
+        }
+    }
 }
 
 pub mod token {
     use base64::prelude::*;
-    use std::sync::LazyLock;
 
     fn signature(claim: &str, secret: impl AsRef<[u8]>) -> String {
         use sha3::{Digest, Sha3_256};
@@ -63,11 +342,10 @@ pub mod token {
         BASE64_URL_SAFE_NO_PAD.encode(hasher.finalize())
     }
 
-    pub static TOKEN_SECRET: LazyLock<[u8; 32]> = LazyLock::new(|| rand::random());
-
     pub struct Token;
 
     impl Token {
+        #[allow(clippy::new_ret_no_self)]
         pub fn new(sub: &str, age: i64, secret: impl AsRef<[u8]>) -> String {
             let now = time::UtcDateTime::now().unix_timestamp();
             let exp = now + age;
@@ -1,78 +1,145 @@
-use axum::extract::Request;
-use axum::middleware::Next;
-use axum::response::Response;
-use axum::routing::{delete, get, post, put};
-use axum::{Router, middleware};
-use axum_extra::extract::cookie::CookieJar;
-use redb::Database;
+use redb::{Database, ReadOnlyDatabase};
 use std::error::Error;
+use std::path::Path;
 use std::sync::Arc;
 use tokio::net::TcpListener;
-use tower_http::compression::CompressionLayer;
-use tower_http::services::ServeDir;
 
-use note::config::CONFIG;
-use note::handlers::*;
-use note::token::{TOKEN_SECRET, Token};
+use note::config::{self, Config};
+use note::models::{backup, fsck, pages, schema, trash};
+use note::token::Token;
+
+const USAGE: &str = "\
+usage: note [command]
+
+commands:
+  serve              run the server (default)
+  backup [path]      write a snapshot of the database (server must be stopped,
+                     use POST /admin/backup while it runs)
+  restore <path>     verify a snapshot and swap it in (server must be stopped)
+  fsck [--repair]    check users and pages for inconsistencies (server must be
+                     stopped, use /admin/fsck while it runs)
+  rerender           re-render pages from an older markdown pipeline (server
+                     must be stopped, use POST /admin/rerender while it runs)
+
+with several sites, backup/fsck/rerender run for each of them, and
+`backup <path>` and `restore <path>` take the site (\"host/prefix\") last";
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn Error>> {
-    let listener = TcpListener::bind(CONFIG.server_addr).await.unwrap();
-    let db = Database::create(CONFIG.database_path)?;
-
-    let root_invite = Token::new("", 900, CONFIG.secret_invite);
-    println!("Root invite code: {}invite/{root_invite}", CONFIG.base_url);
+    let args: Vec<String> = std::env::args().collect();
+    let path = std::env::var("NOTE_CONFIG").unwrap_or_else(|_| "server.toml".to_string());
+    let sites = config::load_sites(&path)?;
+    let command: Vec<&str> = args.iter().skip(1).map(String::as_str).collect();
+    match command[..] {
+        [] | ["serve"] => serve(sites).await,
+        ["backup"] => {
+            for config in &sites {
+                let db = ReadOnlyDatabase::open(&config.database_path)?;
+                backup::backup(&db, config).map_err(|e| format!("{e:?}"))?;
+            }
+            Ok(())
+        }
+        ["backup", path] | ["backup", path, _] => {
+            let config = pick(&sites, command.get(2))?;
+            let db = ReadOnlyDatabase::open(&config.database_path)?;
+            backup::snapshot(&db, Path::new(path)).map_err(|e| format!("{e:?}"))?;
+            Ok(())
+        }
+        ["restore", path] | ["restore", path, _] => {
+            let config = pick(&sites, command.get(2))?;
+            let previous = backup::restore(Path::new(path), Path::new(&config.database_path))
+                .map_err(|e| format!("{e:?}"))?;
+            println!("Previous database kept at {}", previous.display());
+            Ok(())
+        }
+        ["fsck"] | ["fsck", "--repair"] => {
+            let mut clean = true;
+            for config in &sites {
+                if sites.len() > 1 {
+                    println!("[{}]", config.site_key());
+                }
+                let db = Database::open(&config.database_path)?;
+                schema::migrate(&db).map_err(|e| format!("{e:?}"))?;
+                let report = fsck::check(&db, command.len() > 1).map_err(|e| format!("{e:?}"))?;
+                let sections = [
+                    ("orphan page", &report.orphan_pages),
+                    ("dangling file", &report.dangling_files),
+                    ("dangling collaborator", &report.dangling_collabs),
+                    ("stale html", &report.stale_html),
+                    ("stale index", &report.stale_index),
+                ];
+                for (kind, items) in sections {
+                    items.iter().for_each(|item| println!("{kind}: {item}"));
+                }
+                match (report.is_clean(), report.repaired) {
+                    (true, _) => println!("Database is consistent"),
+                    (false, true) => println!("Database repaired"),
+                    (false, false) => clean = false,
+                }
+            }
+            if !clean {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        ["rerender"] => {
+            for config in &sites {
+                let db = Database::open(&config.database_path)?;
+                schema::migrate(&db).map_err(|e| format!("{e:?}"))?;
+                pages::rerender_all(&db).map_err(|e| format!("{e:?}"))?;
+            }
+            Ok(())
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    }
+}
 
-    // home page & work space
-    let app = Router::new().route("/", get(home_page));
+/// the site named `key`, or the only one
+fn pick<'a>(sites: &'a [Config], key: Option<&&str>) -> std::result::Result<&'a Config, String> {
+    let keys = || {
+        sites
+            .iter()
+            .map(Config::site_key)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match key {
+        Some(key) => sites
+            .iter()
+            .find(|config| config.site_key() == *key)
+            .ok_or_else(|| format!("no site {key}, expected one of: {}", keys())),
+        None if sites.len() == 1 => Ok(&sites[0]),
+        None => Err(format!("name the site, one of: {}", keys())),
+    }
+}
 
-    let app = app // auth
-        .route("/auth", get(auth_page)) // html or redirect
-        .route("/auth/", get(auth_page)) // html or redirect
-        .route("/auth/sign-out", get(sign_out_handler)) // [] -> cookie
-        .route("/auth/sign-out/", get(sign_out_handler)) // [] -> cookie
-        .route("/auth/sign-in", post(sign_in_handler)) // [user, passwd] -> cookie
-        .route("/auth/sign-in/", post(sign_in_handler)) // [user, passwd] -> cookie
-        .route("/auth/sign-up", post(sign_up_handler)) // [user, passwd, invite_code] -> cookie
-        .route("/auth/sign-up/", post(sign_up_handler)) // [user, passwd, invite_code] -> cookie
-        .route("/invite/{invite_code}", get(invite_handler)) // html or redirect
-        .route("/invite/{invite_code}/", get(invite_handler)); // html or redirect
+async fn serve(sites: Vec<Config>) -> std::result::Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(&sites[0].server_addr).await.unwrap();
 
-    let app = app // user
-        .route("/@{user}", get(user_page)) // html
-        .route("/@{user}/", get(user_page)); // html
+    let mut apps = Vec::new();
+    for config in sites {
+        let db = Arc::new(Database::create(&config.database_path)?);
 
-    let app = app // page
-        .route("/@{user}/{page}", get(page_view)) // html
-        .route("/@{user}/{page}/", get(page_view)) // html
-        .route("/@{user}/{page}/edit", get(page_editor)) // html
-        .route("/@{user}/{page}/edit/", get(page_editor)) // html
-        .route("/page/{user}/{page}", put(page_create)) // [] -> ok
-        .route("/page/{user}/{page}/", put(page_create)) // [] -> ok
-        .route("/page/{user}/{page}", post(page_update)) // [title, markdown] -> ok
-        .route("/page/{user}/{page}/", post(page_update)) // [title, markdown] -> ok
-        .route("/page/{user}/{page}", delete(page_delete)) // [] -> ok
-        .route("/page/{user}/{page}/", delete(page_delete)); // [] -> ok
+        if config.backup_interval > 0 {
+            tokio::spawn(backup::schedule(db.clone(), Arc::new(config.clone())));
+        }
+        if config.trash_days > 0 {
+            tokio::spawn(trash::schedule(db.clone(), Arc::new(config.clone())));
+        }
 
-    let app = app
-        .fallback_service(ServeDir::new(CONFIG.site_root))
-        .layer(middleware::from_fn(auth_middleware))
-        .layer(CompressionLayer::new().zstd(true).gzip(true).deflate(true))
-        .with_state(Arc::new(db));
+        let root_invite = Token::new("", 900, &config.secret_invite);
+        println!("Root invite code: {}invite/{root_invite}", config.base_url);
+        apps.push((config, db));
+    }
 
+    let app = note::sites(apps).map_err(|e| format!("{e:?}"))?;
     axum::serve(listener, app).await.unwrap();
     Ok(())
 }
 
-pub async fn auth_middleware(jar: CookieJar, mut request: Request, next: Next) -> Response {
-    let auth: Option<String> = jar
-        .get("token")
-        .and_then(|cookie| Token::parse(cookie.value(), &TOKEN_SECRET.as_ref()));
-
-    request.extensions_mut().insert(auth);
-    next.run(request).await
-}
-
 #[cfg(test)]
 mod tests {
     #[test]
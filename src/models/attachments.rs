@@ -0,0 +1,217 @@
+use crate::models::types::Result;
+use redb::{ReadableTable, Table, TableDefinition, WriteTransaction};
+
+/// (mime, date, bytes)
+type Row<'a> = (&'a str, i64, &'a [u8]);
+
+/// (user, file, name): (mime, date, bytes), files attached to a page
+pub const ATTACHMENTS: TableDefinition<(&str, &str, &str), Row> =
+    TableDefinition::new("attachments");
+
+/// (user, file, deleted_at, name): (mime, date, bytes), attachments of pages in the trash
+pub const TRASHED_ATTACHMENTS: TableDefinition<(&str, &str, i64, &str), Row> =
+    TableDefinition::new("trashed_attachments");
+
+/// an attached file, as handed out by a `Store`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attachment {
+    /// sniffed from `bytes` on upload
+    pub mime: String,
+    pub date: i64,
+    pub bytes: Vec<u8>,
+}
+
+impl Attachment {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self {
+            mime: sniff(bytes).to_string(),
+            date: time::UtcDateTime::now().unix_timestamp(),
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    fn as_row(&self) -> Row<'_> {
+        (&self.mime, self.date, &self.bytes)
+    }
+}
+
+impl From<Row<'_>> for Attachment {
+    fn from((mime, date, bytes): Row<'_>) -> Self {
+        Self {
+            mime: mime.to_string(),
+            date,
+            bytes: bytes.to_vec(),
+        }
+    }
+}
+
+/// MIME type from the content, whatever the upload claimed
+///
+/// text (html and svg included) is served as plain text, so uploads can't run
+/// scripts on the site
+pub fn sniff(bytes: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+    ];
+    if let Some((_, mime)) = SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+    {
+        return mime;
+    }
+    if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) if !text.contains('\0') => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// "shot.png", no path and no hidden files
+pub fn validate_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        && (1..=120).contains(&name.len())
+        && !name.starts_with('.')
+}
+
+/// remove and return the attachments of (user, file)
+fn take(
+    table: &mut Table<(&str, &str, &str), Row>,
+    user: &str,
+    file: &str,
+) -> Result<Vec<(String, Attachment)>> {
+    let mut names = vec![];
+    for entry in table.range((user, file, "")..)? {
+        let (key, _) = entry?;
+        let (entry_user, entry_file, name) = key.value();
+        if (entry_user, entry_file) != (user, file) {
+            break;
+        }
+        names.push(name.to_string());
+    }
+    let mut rows = vec![];
+    for name in names {
+        if let Some(row) = table.remove((user, file, name.as_str()))? {
+            rows.push((name, row.value().into()));
+        }
+    }
+    Ok(rows)
+}
+
+/// remove and return the attachments (user, file) had when deleted at `deleted_at`
+fn take_trashed(
+    table: &mut Table<(&str, &str, i64, &str), Row>,
+    user: &str,
+    file: &str,
+    deleted_at: i64,
+) -> Result<Vec<(String, Attachment)>> {
+    let mut names = vec![];
+    for entry in table.range((user, file, deleted_at, "")..)? {
+        let (key, _) = entry?;
+        let (entry_user, entry_file, entry_deleted_at, name) = key.value();
+        if (entry_user, entry_file, entry_deleted_at) != (user, file, deleted_at) {
+            break;
+        }
+        names.push(name.to_string());
+    }
+    let mut rows = vec![];
+    for name in names {
+        if let Some(row) = table.remove((user, file, deleted_at, name.as_str()))? {
+            rows.push((name, row.value().into()));
+        }
+    }
+    Ok(rows)
+}
+
+/// the page went to the trash, its attachments go with it
+pub fn trash(txn: &WriteTransaction, user: &str, file: &str, deleted_at: i64) -> Result<()> {
+    let rows = take(&mut txn.open_table(ATTACHMENTS)?, user, file)?;
+    let mut trashed = txn.open_table(TRASHED_ATTACHMENTS)?;
+    for (name, attachment) in &rows {
+        trashed.insert((user, file, deleted_at, name.as_str()), attachment.as_row())?;
+    }
+    Ok(())
+}
+
+/// the page came back from the trash
+pub fn restore(txn: &WriteTransaction, user: &str, file: &str, deleted_at: i64) -> Result<()> {
+    let rows = take_trashed(
+        &mut txn.open_table(TRASHED_ATTACHMENTS)?,
+        user,
+        file,
+        deleted_at,
+    )?;
+    let mut attachments = txn.open_table(ATTACHMENTS)?;
+    for (name, attachment) in &rows {
+        attachments.insert((user, file, name.as_str()), attachment.as_row())?;
+    }
+    Ok(())
+}
+
+/// the page was purged from the trash, return how many attachments went
+pub fn purge(txn: &WriteTransaction, user: &str, file: &str, deleted_at: i64) -> Result<usize> {
+    let mut trashed = txn.open_table(TRASHED_ATTACHMENTS)?;
+    Ok(take_trashed(&mut trashed, user, file, deleted_at)?.len())
+}
+
+/// the page is gone for good, so are its attachments; return how many went
+pub fn remove(txn: &WriteTransaction, user: &str, file: &str) -> Result<usize> {
+    let mut attachments = txn.open_table(ATTACHMENTS)?;
+    Ok(take(&mut attachments, user, file)?.len())
+}
+
+/// the page moved to (to_user, to_file)
+pub fn rename(
+    txn: &WriteTransaction,
+    user: &str,
+    file: &str,
+    to_user: &str,
+    to_file: &str,
+) -> Result<()> {
+    let mut attachments = txn.open_table(ATTACHMENTS)?;
+    for (name, attachment) in take(&mut attachments, user, file)? {
+        attachments.insert((to_user, to_file, name.as_str()), attachment.as_row())?;
+    }
+    Ok(())
+}
+
+/// store `attachment` as `name` on (user, file), replacing one with that name
+pub fn insert(
+    txn: &WriteTransaction,
+    user: &str,
+    file: &str,
+    name: &str,
+    attachment: &Attachment,
+) -> Result<()> {
+    let mut attachments = txn.open_table(ATTACHMENTS)?;
+    attachments.insert((user, file, name), attachment.as_row())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_content() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), "image/png");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff(b"%PDF-1.7"), "application/pdf");
+        assert_eq!(
+            sniff(b"<script>alert(1)</script>"),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(sniff(b"\0\x01\x02"), "application/octet-stream");
+
+        assert!(validate_name("shot-1.png"));
+        for name in ["", ".env", "a/b", "a b", &"x".repeat(121)] {
+            assert!(!validate_name(name));
+        }
+    }
+}
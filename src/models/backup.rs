@@ -0,0 +1,248 @@
+use crate::config::Config;
+use crate::models::attachments::{ATTACHMENTS, TRASHED_ATTACHMENTS};
+use crate::models::drafts::DRAFTS;
+use crate::models::pages::{ALIASES, PAGES, PAGES_BY_DATE, SERIES, TAGS};
+use crate::models::schema::{META, SCHEMA_VERSION};
+use crate::models::trash::TRASH;
+use crate::models::types::{Ex, Result};
+use crate::models::users::USERS;
+use redb::{
+    Database, Key, ReadTransaction, ReadableDatabase, ReadableTable, ReadableTableMetadata,
+    TableDefinition, TableError, Value, WriteTransaction,
+};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// write a consistent snapshot of `db` to `path`
+///
+/// everything is copied from a single read transaction into a fresh database,
+/// which is renamed into place only once it has been committed
+pub fn snapshot(db: &impl ReadableDatabase, path: &Path) -> Result<u64> {
+    // left over by a crashed run, its rows must not end up in this snapshot
+    let tmp_path = path.with_extension("tmp");
+    match std::fs::remove_file(&tmp_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let read_txn = db.begin_read()?;
+    let target = Database::create(&tmp_path)?;
+    let write_txn = target.begin_write()?;
+
+    // keep in sync with `verify`
+    let rows = copy_table(&read_txn, &write_txn, META)?
+        + copy_table(&read_txn, &write_txn, USERS)?
+        + copy_table(&read_txn, &write_txn, PAGES)?
+        + copy_table(&read_txn, &write_txn, PAGES_BY_DATE)?
+        + copy_table(&read_txn, &write_txn, TAGS)?
+        + copy_table(&read_txn, &write_txn, SERIES)?
+        + copy_table(&read_txn, &write_txn, ATTACHMENTS)?
+        + copy_table(&read_txn, &write_txn, TRASHED_ATTACHMENTS)?
+        + copy_table(&read_txn, &write_txn, DRAFTS)?
+        + copy_table(&read_txn, &write_txn, ALIASES)?
+        + copy_table(&read_txn, &write_txn, TRASH)?;
+
+    write_txn.commit()?;
+    drop(target);
+    std::fs::rename(&tmp_path, path)?;
+    println!("Wrote snapshot: {} ({rows} rows)", path.display());
+    Ok(rows)
+}
+
+/// check that `path` is a readable snapshot, return the number of rows
+pub fn verify(path: &Path) -> Result<u64> {
+    let mut db = Database::open(path).map_err(|_| Ex::InvalidSnapshot)?;
+    if !db.check_integrity().map_err(|_| Ex::InvalidSnapshot)? {
+        return Err(Ex::InvalidSnapshot);
+    }
+
+    // only snapshots this build can read (or migrate)
+    let read_txn = db.begin_read()?;
+    let version = match read_txn.open_table(META) {
+        Ok(meta) => meta.get("schema_version")?.map_or(0, |v| v.value()),
+        Err(_) => return Err(Ex::InvalidSnapshot),
+    };
+    if version != SCHEMA_VERSION {
+        return Err(Ex::InvalidSnapshot);
+    }
+
+    // keep in sync with `snapshot`
+    let rows = count_table(&read_txn, META)?
+        + count_table(&read_txn, USERS)?
+        + count_table(&read_txn, PAGES)?
+        + count_table(&read_txn, PAGES_BY_DATE)?
+        + count_table(&read_txn, TAGS)?
+        + count_table(&read_txn, SERIES)?
+        + count_table(&read_txn, ATTACHMENTS)?
+        + count_table(&read_txn, TRASHED_ATTACHMENTS)?
+        + count_table(&read_txn, DRAFTS)?
+        + count_table(&read_txn, ALIASES)?
+        + count_table(&read_txn, TRASH)?;
+    Ok(rows)
+}
+
+/// replace the database at `database_path` with a verified copy of `snapshot`
+///
+/// the server must be stopped, the previous database is kept next to it as `.bak`
+pub fn restore(snapshot: &Path, database_path: &Path) -> Result<PathBuf> {
+    verify(snapshot)?;
+
+    // stage a copy next to the database so the final rename is atomic
+    let staged = database_path.with_extension("restore");
+    std::fs::copy(snapshot, &staged)?;
+    verify(&staged)?;
+
+    let now = time::UtcDateTime::now().unix_timestamp();
+    let previous = database_path.with_extension(format!("{now}.bak"));
+    if database_path.exists() {
+        // fails if a running server holds the database, opening it instead
+        // would also fail on the corrupt databases restores are for
+        let live = std::fs::File::open(database_path)?;
+        live.try_lock().map_err(|_| Ex::DatabaseError)?;
+        drop(live);
+        std::fs::rename(database_path, &previous)?;
+    }
+    std::fs::rename(&staged, database_path)?;
+    println!(
+        "Restored {} from {}",
+        database_path.display(),
+        snapshot.display()
+    );
+    Ok(previous)
+}
+
+/// snapshot into `config.backup_dir` and prune old backups
+pub fn backup(db: &impl ReadableDatabase, config: &Config) -> Result<PathBuf> {
+    let dir = Path::new(&config.backup_dir);
+    std::fs::create_dir_all(dir)?;
+
+    let now = time::UtcDateTime::now().unix_timestamp();
+    let path = dir.join(format!("note-{now}.redb"));
+    snapshot(db, &path)?;
+    prune(dir, config.backup_retention)?;
+    Ok(path)
+}
+
+/// keep only the newest `retention` backups in `dir`
+fn prune(dir: &Path, retention: usize) -> Result<()> {
+    let mut backups: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default();
+            name.starts_with("note-") && name.ends_with(".redb")
+        })
+        .collect();
+
+    // timestamps have the same width, so names sort by age
+    backups.sort();
+    let excess = backups.len().saturating_sub(retention);
+    for path in &backups[..excess] {
+        std::fs::remove_file(path)?;
+        println!("Pruned backup: {}", path.display());
+    }
+    Ok(())
+}
+
+/// periodic backups, runs forever
+pub async fn schedule(db: Arc<Database>, config: Arc<Config>) {
+    let period = std::time::Duration::from_secs(config.backup_interval);
+    let mut interval = tokio::time::interval(period);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+        let (db, config) = (db.clone(), config.clone());
+        match tokio::task::spawn_blocking(move || backup(db.as_ref(), &config)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => eprintln!("Scheduled backup failed: {e:?}"),
+            Err(e) => eprintln!("Scheduled backup panicked: {e}"),
+        }
+    }
+}
+
+fn copy_table<K: Key + 'static, V: Value + 'static>(
+    src: &ReadTransaction,
+    dst: &WriteTransaction,
+    definition: TableDefinition<K, V>,
+) -> Result<u64> {
+    let src_table = match src.open_table(definition) {
+        Ok(table) => table,
+        Err(TableError::TableDoesNotExist(_)) => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut dst_table = dst.open_table(definition)?;
+    for entry in src_table.iter()? {
+        let (key, value) = entry?;
+        dst_table.insert(key.value(), value.value())?;
+    }
+    Ok(src_table.len()?)
+}
+
+fn count_table<K: Key + 'static, V: Value + 'static>(
+    txn: &ReadTransaction,
+    definition: TableDefinition<K, V>,
+) -> Result<u64> {
+    let table = match txn.open_table(definition) {
+        Ok(table) => table,
+        Err(TableError::TableDoesNotExist(_)) => return Ok(0),
+        Err(_) => return Err(Ex::InvalidSnapshot),
+    };
+    // decode every row, a snapshot that can't be read back is useless
+    for entry in table.iter()? {
+        let (key, value) = entry?;
+        let _ = (key.value(), value.value());
+    }
+    Ok(table.len()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_and_restore() {
+        let dir = std::env::temp_dir().join(format!("note-backup-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let live = dir.join("live.redb");
+        let db = Database::create(&live).unwrap();
+        crate::models::schema::migrate(&db).unwrap();
+        let write_txn = db.begin_write().unwrap();
+        write_txn
+            .open_table(ALIASES)
+            .unwrap()
+            .insert(("alice", "old"), ("alice", "new"))
+            .unwrap();
+        write_txn.commit().unwrap();
+
+        // a stale tmp file from a crashed run, with a row of its own
+        let snapshot_path = dir.join("snap.redb");
+        let stale = Database::create(snapshot_path.with_extension("tmp")).unwrap();
+        let write_txn = stale.begin_write().unwrap();
+        write_txn
+            .open_table(ALIASES)
+            .unwrap()
+            .insert(("eve", "stale"), ("eve", "stale"))
+            .unwrap();
+        write_txn.commit().unwrap();
+        drop(stale);
+
+        let rows = snapshot(&db, &snapshot_path).unwrap();
+        assert_eq!(rows, verify(&snapshot_path).unwrap());
+        let copy = Database::open(&snapshot_path).unwrap();
+        let read_txn = copy.begin_read().unwrap();
+        assert_eq!(read_txn.open_table(ALIASES).unwrap().len().unwrap(), 1);
+        drop((read_txn, copy));
+
+        // refused while the database is open, fine once it is corrupt
+        assert!(restore(&snapshot_path, &live).is_err());
+        drop(db);
+        std::fs::write(&live, b"not a database").unwrap();
+        let previous = restore(&snapshot_path, &live).unwrap();
+        assert_eq!(std::fs::read(&previous).unwrap(), b"not a database");
+        assert!(verify(&live).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use crate::models::types::{Ex, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// longest document editors can grow a page to, in chars
+pub const MAX_DOCUMENT_CHARS: usize = 1 << 20;
+
+/// edits kept for transforming late ones, older edits are rejected
+const MAX_HISTORY: usize = 1024;
+
+/// one step of an `Op`, counted in chars
+///
+/// on the wire (like ot.js) `3` keeps 3 chars, `-3` deletes them and `"abc"`
+/// inserts "abc"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Wire", into = "Wire")]
+pub enum Part {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Wire {
+    Count(i64),
+    Text(String),
+}
+
+impl TryFrom<Wire> for Part {
+    type Error = &'static str;
+
+    /// counts come from editors, none can be longer than a document
+    fn try_from(wire: Wire) -> std::result::Result<Self, Self::Error> {
+        match wire {
+            Wire::Count(n) if n.unsigned_abs() > MAX_DOCUMENT_CHARS as u64 => {
+                Err("count longer than a document")
+            }
+            Wire::Count(n) if n < 0 => Ok(Part::Delete(n.unsigned_abs() as usize)),
+            Wire::Count(n) => Ok(Part::Retain(n as usize)),
+            Wire::Text(text) => Ok(Part::Insert(text)),
+        }
+    }
+}
+
+impl From<Part> for Wire {
+    fn from(part: Part) -> Self {
+        match part {
+            Part::Retain(n) => Wire::Count(n as i64),
+            Part::Insert(text) => Wire::Text(text),
+            Part::Delete(n) => Wire::Count(-(n as i64)),
+        }
+    }
+}
+
+impl Part {
+    /// chars of the document this part reads (retain, delete) or writes (insert)
+    fn len(&self) -> usize {
+        match self {
+            Part::Retain(n) | Part::Delete(n) => *n,
+            Part::Insert(text) => text.chars().count(),
+        }
+    }
+
+    /// what is left of a retain or delete after its first `n` chars
+    fn skip(&self, n: usize) -> Option<Part> {
+        match self {
+            Part::Retain(len) if *len > n => Some(Part::Retain(len - n)),
+            Part::Delete(len) if *len > n => Some(Part::Delete(len - n)),
+            _ => None,
+        }
+    }
+}
+
+/// an edit of a whole document, walking it from start to end
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<Part>")]
+pub struct Op(pub Vec<Part>);
+
+impl From<Vec<Part>> for Op {
+    fn from(parts: Vec<Part>) -> Self {
+        let mut op = Op::default();
+        for part in parts {
+            match part {
+                Part::Retain(n) => op.retain(n),
+                Part::Insert(text) => op.insert(&text),
+                Part::Delete(n) => op.delete(n),
+            }
+        }
+        op
+    }
+}
+
+impl Op {
+    /// merged into the last part when the sum fits, too long ops fail in `apply`
+    pub fn retain(&mut self, n: usize) {
+        match self.0.last_mut() {
+            _ if n == 0 => {}
+            Some(Part::Retain(last)) if last.checked_add(n).is_some() => *last += n,
+            _ => self.0.push(Part::Retain(n)),
+        }
+    }
+
+    /// inserts go before a delete at the same spot, so equal edits look the same
+    pub fn insert(&mut self, text: &str) {
+        match self.0.as_mut_slice() {
+            _ if text.is_empty() => {}
+            [.., Part::Insert(last)] | [.., Part::Insert(last), Part::Delete(_)] => {
+                last.push_str(text)
+            }
+            [.., Part::Delete(_)] => {
+                let at = self.0.len() - 1;
+                self.0.insert(at, Part::Insert(text.to_string()));
+            }
+            _ => self.0.push(Part::Insert(text.to_string())),
+        }
+    }
+
+    pub fn delete(&mut self, n: usize) {
+        match self.0.last_mut() {
+            _ if n == 0 => {}
+            Some(Part::Delete(last)) if last.checked_add(n).is_some() => *last += n,
+            _ => self.0.push(Part::Delete(n)),
+        }
+    }
+
+    /// chars of the document this applies to
+    pub fn base_len(&self) -> Result<usize> {
+        self.0
+            .iter()
+            .filter(|part| !matches!(part, Part::Insert(_)))
+            .try_fold(0usize, |len, part| len.checked_add(part.len()))
+            .ok_or(Ex::InvalidEdit)
+    }
+
+    pub fn apply(&self, text: &[char]) -> Result<Vec<char>> {
+        if self.base_len()? != text.len() {
+            return Err(Ex::InvalidEdit);
+        }
+        let mut out = Vec::with_capacity(text.len());
+        let mut at = 0usize;
+        for part in &self.0 {
+            match part {
+                Part::Retain(n) => {
+                    let end = at.checked_add(*n).ok_or(Ex::InvalidEdit)?;
+                    out.extend_from_slice(text.get(at..end).ok_or(Ex::InvalidEdit)?);
+                    at = end;
+                }
+                Part::Insert(inserted) => out.extend(inserted.chars()),
+                Part::Delete(n) => at = at.checked_add(*n).ok_or(Ex::InvalidEdit)?,
+            }
+        }
+        Ok(out)
+    }
+
+    /// (a', b') of two edits of the same document, so that a then b' and b then
+    /// a' end up with the same text
+    ///
+    /// when both insert at the same spot, `a` goes first
+    pub fn transform(a: &Op, b: &Op) -> Result<(Op, Op)> {
+        if a.base_len()? != b.base_len()? {
+            return Err(Ex::InvalidEdit);
+        }
+        let (mut a2, mut b2) = (Op::default(), Op::default());
+        let (mut a_parts, mut b_parts) = (a.0.iter().cloned(), b.0.iter().cloned());
+        let (mut pa, mut pb) = (a_parts.next(), b_parts.next());
+        loop {
+            match (&pa, &pb) {
+                (None, None) => break,
+                (Some(Part::Insert(text)), _) => {
+                    a2.insert(text);
+                    b2.retain(text.chars().count());
+                    pa = a_parts.next();
+                }
+                (_, Some(Part::Insert(text))) => {
+                    a2.retain(text.chars().count());
+                    b2.insert(text);
+                    pb = b_parts.next();
+                }
+                (Some(x), Some(y)) => {
+                    let n = x.len().min(y.len());
+                    match (x, y) {
+                        (Part::Retain(_), Part::Retain(_)) => {
+                            a2.retain(n);
+                            b2.retain(n);
+                        }
+                        (Part::Delete(_), Part::Retain(_)) => a2.delete(n),
+                        (Part::Retain(_), Part::Delete(_)) => b2.delete(n),
+                        // deleted by both, gone either way
+                        _ => {}
+                    }
+                    let (rest_a, rest_b) = (x.skip(n), y.skip(n));
+                    pa = rest_a.or_else(|| a_parts.next());
+                    pb = rest_b.or_else(|| b_parts.next());
+                }
+                _ => return Err(Ex::InvalidEdit),
+            }
+        }
+        Ok((a2, b2))
+    }
+
+    /// where a cursor at `index` ends up after this edit, text inserted right at
+    /// the cursor goes after it
+    pub fn transform_index(&self, index: usize) -> usize {
+        let (mut at, mut moved) = (0, index);
+        for part in &self.0 {
+            if at >= index {
+                break;
+            }
+            match part {
+                Part::Retain(n) => at += n,
+                Part::Insert(text) => moved += text.chars().count(),
+                Part::Delete(n) => {
+                    moved -= (index - at).min(*n);
+                    at += n;
+                }
+            }
+        }
+        moved
+    }
+}
+
+/// the text every editor of a page converges on, and the last edits to it
+pub struct Document {
+    text: Vec<char>,
+    rev: u64,
+    /// edits up to `rev`, the oldest one made on revision `rev - history.len()`
+    history: VecDeque<Op>,
+}
+
+impl Document {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: text.chars().collect(),
+            rev: 0,
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// edits applied so far
+    pub fn rev(&self) -> u64 {
+        self.rev
+    }
+
+    /// apply `op`, made on revision `rev`, after the edits its editor hadn't
+    /// seen yet, and return it as applied
+    pub fn edit(&mut self, rev: u64, mut op: Op) -> Result<Op> {
+        let unseen = self.rev.checked_sub(rev).ok_or(Ex::InvalidEdit)? as usize;
+        if unseen > self.history.len() {
+            return Err(Ex::InvalidEdit);
+        }
+        for concurrent in self.history.range(self.history.len() - unseen..) {
+            op = Op::transform(&op, concurrent)?.0;
+        }
+        let text = op.apply(&self.text)?;
+        if text.len() > MAX_DOCUMENT_CHARS && text.len() > self.text.len() {
+            return Err(Ex::ContentTooLarge);
+        }
+
+        self.text = text;
+        self.rev += 1;
+        self.history.push_back(op.clone());
+        if self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the edit of `from` that splices `insert` over chars at..at + delete
+    fn splice(from: &str, at: usize, delete: usize, insert: &str) -> Op {
+        let len = from.chars().count();
+        Op::from(vec![
+            Part::Retain(at),
+            Part::Delete(delete),
+            Part::Insert(insert.to_string()),
+            Part::Retain(len - at - delete),
+        ])
+    }
+
+    fn apply(op: &Op, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        op.apply(&chars).unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn concurrent_edits_converge() {
+        let text = "the quick brown fox";
+        let cases = [
+            // (a, b, merged)
+            ((4, 0, "very "), (10, 6, "red "), "the very quick red fox"),
+            ((4, 6, ""), (10, 6, ""), "the fox"),
+            ((4, 11, "slow"), (10, 6, "red "), "the slowred fox"),
+            ((0, 0, "a"), (0, 0, "b"), "abthe quick brown fox"),
+            ((19, 0, "es"), (16, 3, "🦊"), "the quick brown 🦊es"),
+        ];
+        for ((at, delete, insert), (b_at, b_delete, b_insert), merged) in cases {
+            let a = splice(text, at, delete, insert);
+            let b = splice(text, b_at, b_delete, b_insert);
+            let (a2, b2) = Op::transform(&a, &b).unwrap();
+            assert_eq!(apply(&b2, &apply(&a, text)), merged);
+            assert_eq!(apply(&a2, &apply(&b, text)), merged);
+        }
+
+        let short = splice("abc", 0, 0, "x");
+        assert!(matches!(
+            Op::transform(&short, &splice(text, 0, 0, "x")),
+            Err(Ex::InvalidEdit)
+        ));
+    }
+
+    #[test]
+    fn document_catches_up_late_edits() {
+        let mut doc = Document::new("hello");
+        // both editors saw revision 0
+        doc.edit(0, splice("hello", 5, 0, " world")).unwrap();
+        let applied = doc.edit(0, splice("hello", 0, 1, "J")).unwrap();
+        assert_eq!(applied, splice("hello world", 0, 1, "J"));
+        assert_eq!((doc.text().as_str(), doc.rev()), ("Jello world", 2));
+
+        // from the future, or for the wrong text
+        assert!(doc.edit(3, splice("Jello world", 0, 0, "!")).is_err());
+        assert!(doc.edit(2, splice("Jello", 0, 0, "!")).is_err());
+        assert_eq!(doc.rev(), 2);
+    }
+
+    #[test]
+    fn cursors_follow_edits() {
+        let op = splice("hello world", 0, 6, "J");
+        assert_eq!(op.transform_index(0), 0);
+        assert_eq!(op.transform_index(3), 1);
+        assert_eq!(op.transform_index(11), 6);
+        // inserted at the cursor, the cursor stays before it
+        assert_eq!(splice("ab", 1, 0, "xyz").transform_index(1), 1);
+    }
+
+    #[test]
+    fn wire_format() {
+        let op = splice("hello", 1, 2, "ey");
+        let json = serde_json::to_string(&op).unwrap();
+        assert_eq!(json, r#"[1,"ey",-2,2]"#);
+        assert_eq!(serde_json::from_str::<Op>(&json).unwrap(), op);
+        // zeros and split parts are tidied up
+        let op: Op = serde_json::from_str(r#"[0,1,1,"a","b",-1,0]"#).unwrap();
+        assert_eq!(op, splice("abc", 2, 1, "ab"));
+    }
+
+    #[test]
+    fn huge_counts_are_rejected() {
+        let max = i64::MAX;
+        let json = format!(r#"[{max}, -{max}, 7]"#);
+        assert!(serde_json::from_str::<Op>(&json).is_err());
+        let json = format!("[{}]", MAX_DOCUMENT_CHARS + 1);
+        assert!(serde_json::from_str::<Op>(&json).is_err());
+
+        // built by hand, the counts still don't wrap or slice past the text
+        let text: Vec<char> = "hello".chars().collect();
+        let op = Op(vec![
+            Part::Retain(usize::MAX),
+            Part::Delete(usize::MAX),
+            Part::Retain(7),
+        ]);
+        assert!(matches!(op.apply(&text), Err(Ex::InvalidEdit)));
+        let op = Op(vec![Part::Delete(usize::MAX - 1), Part::Retain(6)]);
+        assert!(matches!(op.apply(&text), Err(Ex::InvalidEdit)));
+        let mut doc = Document::new("hello");
+        assert!(doc.edit(0, op).is_err());
+        assert_eq!(doc.text(), "hello");
+    }
+}
@@ -0,0 +1,76 @@
+use crate::models::types::Result;
+use redb::{ReadableTable, TableDefinition, WriteTransaction};
+
+/// (user, file, editor): (title, markdown, saved_at), unpublished edits
+pub const DRAFTS: TableDefinition<(&str, &str, &str), (&str, &str, i64)> =
+    TableDefinition::new("drafts");
+
+/// an autosaved edit, as handed out by a `Store`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Draft {
+    pub title: String,
+    pub markdown: String,
+    pub saved_at: i64,
+}
+
+impl Draft {
+    pub fn new(title: &str, markdown: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            markdown: markdown.to_string(),
+            saved_at: time::UtcDateTime::now().unix_timestamp(),
+        }
+    }
+}
+
+impl From<(&str, &str, i64)> for Draft {
+    fn from((title, markdown, saved_at): (&str, &str, i64)) -> Self {
+        Self {
+            title: title.to_string(),
+            markdown: markdown.to_string(),
+            saved_at,
+        }
+    }
+}
+
+/// remove the drafts of (user, file), by every editor, and return them
+pub fn take(txn: &WriteTransaction, user: &str, file: &str) -> Result<Vec<(String, Draft)>> {
+    let mut drafts = txn.open_table(DRAFTS)?;
+    let mut editors = vec![];
+    for entry in drafts.range((user, file, "")..)? {
+        let (key, _) = entry?;
+        let (entry_user, entry_file, editor) = key.value();
+        if (entry_user, entry_file) != (user, file) {
+            break;
+        }
+        editors.push(editor.to_string());
+    }
+    let mut rows = vec![];
+    for editor in editors {
+        if let Some(draft) = drafts.remove((user, file, editor.as_str()))? {
+            rows.push((editor, draft.value().into()));
+        }
+    }
+    Ok(rows)
+}
+
+/// the page moved to (to_user, to_file), its drafts go along
+pub fn rename(
+    txn: &WriteTransaction,
+    user: &str,
+    file: &str,
+    to_user: &str,
+    to_file: &str,
+) -> Result<()> {
+    let rows = take(txn, user, file)?;
+    let mut drafts = txn.open_table(DRAFTS)?;
+    for (editor, draft) in &rows {
+        let value = (
+            draft.title.as_str(),
+            draft.markdown.as_str(),
+            draft.saved_at,
+        );
+        drafts.insert((to_user, to_file, editor.as_str()), value)?;
+    }
+    Ok(())
+}
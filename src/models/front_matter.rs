@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+
+/// metadata block at the top of the markdown, fenced by "---" (yaml) or "+++" (toml)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrontMatter {
+    /// normalized: lowercase, spaces as "-", no duplicates
+    pub tags: Vec<String>,
+    pub summary: Option<String>,
+    /// name of the series (or notebook) the page belongs to
+    pub series: Option<String>,
+    /// position in the series, pages without one come last
+    pub order: Option<i64>,
+    /// every other key, scalars as written and lists joined by ", "
+    pub fields: BTreeMap<String, String>,
+}
+
+/// longest tag kept
+const MAX_TAG_LEN: usize = 64;
+
+/// (front matter, markdown after it)
+///
+/// a block that doesn't parse is left in the markdown, so it shows up on the page
+pub fn split(markdown: &str) -> (Option<FrontMatter>, &str) {
+    let Some((fence, rest)) = markdown
+        .split_once('\n')
+        .filter(|(first, _)| matches!(first.trim_end(), "---" | "+++"))
+    else {
+        return (None, markdown);
+    };
+    let fence = fence.trim_end();
+
+    // find the closing fence, yaml may also end with "..."
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        let end = line.trim_end();
+        if end == fence || (fence == "---" && end == "...") {
+            let block = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+            let parsed = match fence {
+                "---" => from_yaml(block),
+                _ => from_toml(block),
+            };
+            return match parsed {
+                Some(front_matter) => (Some(front_matter), body),
+                None => (None, markdown),
+            };
+        }
+        offset += line.len();
+    }
+    (None, markdown)
+}
+
+/// "Rust Lang " -> "rust-lang", None for anything that can't be part of a url
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase().replace(' ', "-");
+    let valid = tag
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-'));
+    (valid && !tag.is_empty() && tag.len() <= MAX_TAG_LEN).then_some(tag)
+}
+
+impl FrontMatter {
+    /// (url name, order) of the page's series, if the name makes a valid url
+    pub fn series_key(&self) -> Option<(String, i64)> {
+        let series = normalize_tag(self.series.as_deref()?)?;
+        Some((series, self.order.unwrap_or(i64::MAX)))
+    }
+
+    fn new(entries: impl IntoIterator<Item = (String, Vec<String>)>) -> Self {
+        let mut front_matter = Self::default();
+        for (key, values) in entries {
+            match key.as_str() {
+                "tags" => {
+                    // a list, or one comma separated string
+                    for tag in values.iter().flat_map(|v| v.split(',')) {
+                        let tag = normalize_tag(tag);
+                        if let Some(tag) = tag.filter(|t| !front_matter.tags.contains(t)) {
+                            front_matter.tags.push(tag);
+                        }
+                    }
+                }
+                "summary" => front_matter.summary = Some(values.join(", ")),
+                "series" => front_matter.series = Some(values.join(", ")),
+                "order" => front_matter.order = values.join("").trim().parse().ok(),
+                _ => {
+                    front_matter.fields.insert(key, values.join(", "));
+                }
+            }
+        }
+        front_matter
+    }
+}
+
+fn from_yaml(block: &str) -> Option<FrontMatter> {
+    use serde_norway::Value;
+    fn scalar(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+
+    let mapping = match serde_norway::from_str::<Value>(block).ok()? {
+        Value::Mapping(mapping) => mapping,
+        Value::Null => return Some(FrontMatter::default()),
+        _ => return None,
+    };
+    let entries = mapping.iter().filter_map(|(key, value)| {
+        let values = match value {
+            Value::Sequence(items) => items.iter().filter_map(scalar).collect(),
+            value => vec![scalar(value)?],
+        };
+        Some((scalar(key)?, values))
+    });
+    Some(FrontMatter::new(entries))
+}
+
+fn from_toml(block: &str) -> Option<FrontMatter> {
+    use toml::Value;
+    fn scalar(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Datetime(_) => {
+                Some(value.to_string())
+            }
+            _ => None,
+        }
+    }
+
+    let table: toml::Table = toml::from_str(block).ok()?;
+    let entries = table.iter().filter_map(|(key, value)| {
+        let values = match value {
+            Value::Array(items) => items.iter().filter_map(scalar).collect(),
+            value => vec![scalar(value)?],
+        };
+        Some((key.clone(), values))
+    });
+    Some(FrontMatter::new(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml_and_toml() {
+        let markdown =
+            "---\ntags: [Rust, web dev, rust]\nsummary: Notes\nseries: intro\n---\n# Body\n";
+        let (front_matter, body) = split(markdown);
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.tags, ["rust", "web-dev"]);
+        assert_eq!(front_matter.summary.as_deref(), Some("Notes"));
+        assert_eq!(front_matter.series.as_deref(), Some("intro"));
+        assert_eq!(body, "# Body\n");
+
+        let markdown = "+++\r\ntags = \"a, b\"\r\norder = 2\r\n+++\r\nbody";
+        let (front_matter, body) = split(markdown);
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.tags, ["a", "b"]);
+        assert_eq!(front_matter.order, Some(2));
+        assert_eq!(body, "body");
+    }
+
+    #[test]
+    fn left_alone() {
+        // a thematic break, an unclosed block and a block that doesn't parse
+        for markdown in [
+            "text\n---\nmore",
+            "---\ntags: [a]\n",
+            "---\n: [\n---\nbody",
+            "+++\n",
+        ] {
+            assert_eq!(split(markdown), (None, markdown));
+        }
+        assert_eq!(normalize_tag("a/b"), None);
+        assert_eq!(
+            normalize_tag(" Café Au Lait "),
+            Some("café-au-lait".to_string())
+        );
+    }
+}
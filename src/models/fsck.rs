@@ -0,0 +1,355 @@
+use crate::models::pages::{
+    self, ALIASES, PAGES, PAGES_BY_DATE, PageData, RENDERER_VERSION, SERIES, TAGS,
+};
+use crate::models::types::Result;
+use crate::models::users::{USERS, UserData};
+use crate::models::{attachments, drafts, front_matter};
+use redb::{Database, Key, ReadableDatabase, ReadableTable, TableError, WriteTransaction};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// problems found by `check`, as "@user/file" or "@user -> @collab"
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    /// page rows whose owner is gone or doesn't list them in `files`
+    pub orphan_pages: Vec<String>,
+    /// `files` entries without a page row
+    pub dangling_files: Vec<String>,
+    /// `collabs` entries naming a user that doesn't exist
+    pub dangling_collabs: Vec<String>,
+    /// pages rendered by an older pipeline, or whose html differs from rendering now
+    pub stale_html: Vec<String>,
+    /// pages missing from `PAGES_BY_DATE` or listed there under the wrong date,
+    /// tags missing from `TAGS` ("@user/file #tag"), series entries missing from
+    /// `SERIES` ("@user/file in series"), and index rows without a page
+    pub stale_index: Vec<String>,
+    pub repaired: bool,
+}
+
+impl Report {
+    pub fn is_clean(&self) -> bool {
+        self.orphan_pages.is_empty()
+            && self.dangling_files.is_empty()
+            && self.dangling_collabs.is_empty()
+            && self.stale_html.is_empty()
+            && self.stale_index.is_empty()
+    }
+}
+
+/// rows of the index tables, as `check` found them
+#[derive(Default)]
+struct Indexed {
+    dates: BTreeSet<(i64, String, String)>,
+    tags: BTreeSet<(String, String, String)>,
+    series: BTreeSet<(String, String, i64, String)>,
+}
+
+/// ((user, file), title, markdown, html, date) of a page to re-render
+type Rerender = ((String, String), String, String, String, i64);
+
+/// what `check` found, and the rows a repair writes
+struct Findings {
+    report: Report,
+    /// users as they should be, and as they were
+    users: BTreeMap<String, UserData>,
+    original: BTreeMap<String, UserData>,
+    /// (user, file) of pages whose owner is gone
+    orphans: Vec<(String, String)>,
+    stale: Vec<Rerender>,
+}
+
+/// keys of an index table, none if it doesn't exist yet
+fn index_keys<K, T>(
+    table: std::result::Result<impl ReadableTable<K, ()>, TableError>,
+    row: impl for<'a> Fn(K::SelfType<'a>) -> T,
+) -> Result<BTreeSet<T>>
+where
+    K: Key + 'static,
+    T: Ord,
+{
+    let mut keys = BTreeSet::new();
+    if let Ok(table) = table {
+        for entry in table.iter()? {
+            let (key, _) = entry?;
+            keys.insert(row(key.value()));
+        }
+    }
+    Ok(keys)
+}
+
+/// cross-check `USERS` against `PAGES`, and fix what was found if `repair`
+///
+/// repairs keep as much data as possible: orphan pages are relinked to their
+/// owner and only dropped when the owner no longer exists, page dates are kept
+pub fn check(db: &Database, repair: bool) -> Result<Report> {
+    if !repair {
+        let read_txn = db.begin_read()?;
+        let indexed = Indexed {
+            dates: index_keys(read_txn.open_table(PAGES_BY_DATE), |(date, user, file)| {
+                (date, user.to_string(), file.to_string())
+            })?,
+            tags: index_keys(read_txn.open_table(TAGS), |(tag, user, file)| {
+                (tag.to_string(), user.to_string(), file.to_string())
+            })?,
+            series: index_keys(read_txn.open_table(SERIES), |(user, name, order, file)| {
+                (user.to_string(), name.to_string(), order, file.to_string())
+            })?,
+        };
+        let users_table = read_txn.open_table(USERS)?;
+        let pages_table = read_txn.open_table(PAGES)?;
+        return Ok(scan(&users_table, &pages_table, indexed)?.report);
+    }
+
+    let write_txn = db.begin_write()?;
+    let report = {
+        let indexed = Indexed {
+            dates: index_keys(write_txn.open_table(PAGES_BY_DATE), |(date, user, file)| {
+                (date, user.to_string(), file.to_string())
+            })?,
+            tags: index_keys(write_txn.open_table(TAGS), |(tag, user, file)| {
+                (tag.to_string(), user.to_string(), file.to_string())
+            })?,
+            series: index_keys(write_txn.open_table(SERIES), |(user, name, order, file)| {
+                (user.to_string(), name.to_string(), order, file.to_string())
+            })?,
+        };
+        let mut users_table = write_txn.open_table(USERS)?;
+        let mut pages_table = write_txn.open_table(PAGES)?;
+        let mut found = scan(&users_table, &pages_table, indexed)?;
+
+        if !found.report.is_clean() {
+            for (user, data) in &found.users {
+                let before = &found.original[user];
+                if before.files != data.files || before.collabs != data.collabs {
+                    users_table.insert(user.as_str(), data)?;
+                }
+            }
+            for (user, file) in &found.orphans {
+                pages_table.remove((user.as_str(), file.as_str()))?;
+            }
+            for ((user, file), title, markdown, html, date) in &found.stale {
+                let page = PageData {
+                    title,
+                    markdown,
+                    html,
+                    date: *date,
+                    renderer: RENDERER_VERSION,
+                };
+                pages_table.insert((user.as_str(), file.as_str()), page)?;
+            }
+            found.report.repaired = true;
+        }
+        drop((users_table, pages_table));
+        for (user, file) in &found.orphans {
+            drop_page_rows(&write_txn, user, file)?;
+        }
+        found.report
+    };
+
+    // after orphans are gone
+    if report.repaired {
+        pages::reindex(&write_txn)?;
+        write_txn.commit()?;
+    } else {
+        write_txn.abort()?;
+    }
+    Ok(report)
+}
+
+/// attachments, drafts and aliases of a page dropped as an orphan
+fn drop_page_rows(txn: &WriteTransaction, user: &str, file: &str) -> Result<()> {
+    attachments::remove(txn, user, file)?;
+    drafts::take(txn, user, file)?;
+    let mut aliases = txn.open_table(ALIASES)?;
+    let mut pointing = vec![(user.to_string(), file.to_string())];
+    for entry in aliases.iter()? {
+        let (alias, target) = entry?;
+        if target.value() == (user, file) {
+            let (alias_user, alias_file) = alias.value();
+            pointing.push((alias_user.to_string(), alias_file.to_string()));
+        }
+    }
+    for (alias_user, alias_file) in &pointing {
+        aliases.remove((alias_user.as_str(), alias_file.as_str()))?;
+    }
+    Ok(())
+}
+
+/// find what `check` reports, without writing anything
+fn scan(
+    users_table: &impl ReadableTable<&'static str, UserData>,
+    pages_table: &impl ReadableTable<(&'static str, &'static str), PageData<'static>>,
+    indexed: Indexed,
+) -> Result<Findings> {
+    let mut report = Report::default();
+    let mut users: BTreeMap<String, UserData> = BTreeMap::new();
+    for entry in users_table.iter()? {
+        let (user, data) = entry?;
+        users.insert(user.value().to_string(), data.value());
+    }
+    let original = users.clone();
+
+    // pages -> users
+    let mut orphans = vec![];
+    let mut stale = vec![];
+    let mut dates = BTreeSet::new();
+    let mut tags = BTreeSet::new();
+    let mut series = BTreeSet::new();
+    for entry in pages_table.iter()? {
+        let (key, page) = entry?;
+        let (user, file) = key.value();
+        let page = page.value();
+        let key = (user.to_string(), file.to_string());
+        dates.insert((page.date, key.0.clone(), key.1.clone()));
+        let front_matter = front_matter::split(page.markdown).0.unwrap_or_default();
+        if let Some((name, order)) = front_matter.series_key() {
+            series.insert((key.0.clone(), name, order, key.1.clone()));
+        }
+        for tag in front_matter.tags {
+            tags.insert((tag, key.0.clone(), key.1.clone()));
+        }
+
+        match users.get_mut(user) {
+            Some(data) if data.files.contains(file) => {}
+            Some(data) => {
+                report.orphan_pages.push(format!("@{user}/{file}"));
+                data.files.insert(file.to_string());
+            }
+            None => {
+                report.orphan_pages.push(format!("@{user}/{file}"));
+                orphans.push(key);
+                continue;
+            }
+        }
+
+        let mut html = String::new();
+        PageData::render(page.markdown, &mut html);
+        if page.is_stale() || html != page.html {
+            report.stale_html.push(format!("@{user}/{file}"));
+            stale.push((
+                key,
+                page.title.to_string(),
+                page.markdown.to_string(),
+                html,
+                page.date,
+            ));
+        }
+    }
+
+    // pages <-> index
+    for (_, user, file) in dates.symmetric_difference(&indexed.dates) {
+        report.stale_index.push(format!("@{user}/{file}"));
+    }
+    for (tag, user, file) in tags.symmetric_difference(&indexed.tags) {
+        report.stale_index.push(format!("@{user}/{file} #{tag}"));
+    }
+    for (user, name, _, file) in series.symmetric_difference(&indexed.series) {
+        report.stale_index.push(format!("@{user}/{file} in {name}"));
+    }
+
+    // users -> pages, users -> users
+    for (user, data) in users.iter_mut() {
+        let mut dangling = vec![];
+        for file in &data.files {
+            if pages_table.get((user.as_str(), file.as_str()))?.is_none() {
+                dangling.push(file.clone());
+            }
+        }
+        for file in dangling {
+            report.dangling_files.push(format!("@{user}/{file}"));
+            data.files.remove(&file);
+        }
+
+        // one-way edges are fine, an invite only links the inviter's side
+        let dangling: Vec<_> = data
+            .collabs
+            .iter()
+            .filter(|collab| !original.contains_key(*collab))
+            .cloned()
+            .collect();
+        for collab in dangling {
+            report
+                .dangling_collabs
+                .push(format!("@{user} -> @{collab}"));
+            data.collabs.remove(&collab);
+        }
+    }
+
+    Ok(Findings {
+        report,
+        users,
+        original,
+        orphans,
+        stale,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::attachments::{ATTACHMENTS, Attachment};
+    use crate::models::drafts::DRAFTS;
+    use redb::ReadableTableMetadata;
+    use redb::backends::InMemoryBackend;
+
+    fn user(files: &[&str], collabs: &[&str]) -> UserData {
+        let mut data = UserData::default();
+        data.files = files.iter().map(|f| f.to_string()).collect();
+        data.collabs = collabs.iter().map(|c| c.to_string()).collect();
+        data
+    }
+
+    #[test]
+    fn dangling_collabs_and_orphans() {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut users = write_txn.open_table(USERS).unwrap();
+            // carol was invited by alice, she doesn't list alice back
+            let collabs = ["bob", "carol", "mallory"];
+            users.insert("alice", user(&[], &collabs)).unwrap();
+            users.insert("bob", user(&[], &["alice"])).unwrap();
+            users.insert("carol", user(&[], &[])).unwrap();
+            let mut buf = String::new();
+            let page = PageData::new("Gone", "text", &mut buf);
+            let mut pages = write_txn.open_table(PAGES).unwrap();
+            pages.insert(("ghost", "notes"), page).unwrap();
+            let attachment = Attachment::new(b"x");
+            attachments::insert(&write_txn, "ghost", "notes", "x.txt", &attachment).unwrap();
+            let mut drafts = write_txn.open_table(DRAFTS).unwrap();
+            drafts
+                .insert(("ghost", "notes", "alice"), ("Gone", "draft", 0))
+                .unwrap();
+            let mut aliases = write_txn.open_table(ALIASES).unwrap();
+            aliases
+                .insert(("ghost", "old"), ("ghost", "notes"))
+                .unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        // a check alone changes nothing
+        let report = check(&db, false).unwrap();
+        assert_eq!(report.dangling_collabs, ["@alice -> @mallory"]);
+        assert_eq!(report.orphan_pages, ["@ghost/notes"]);
+        assert!(!report.repaired);
+        assert_eq!(check(&db, false).unwrap().dangling_collabs.len(), 1);
+
+        let report = check(&db, true).unwrap();
+        assert!(report.repaired);
+        assert!(check(&db, false).unwrap().is_clean());
+        let read_txn = db.begin_read().unwrap();
+        let users = read_txn.open_table(USERS).unwrap();
+        let alice = users.get("alice").unwrap().unwrap().value();
+        assert_eq!(alice.collabs, user(&[], &["bob", "carol"]).collabs);
+        assert!(alice.check_edit("carol", "alice").is_ok());
+        for len in [
+            read_txn.open_table(ATTACHMENTS).unwrap().len().unwrap(),
+            read_txn.open_table(DRAFTS).unwrap().len().unwrap(),
+            read_txn.open_table(ALIASES).unwrap().len().unwrap(),
+        ] {
+            assert_eq!(len, 0);
+        }
+    }
+}
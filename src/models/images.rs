@@ -0,0 +1,248 @@
+use crate::models::types::{Ex, Result};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+
+/// longest side of a stored image, bigger ones are scaled down
+const MAX_SIDE: u32 = 2048;
+
+/// longest side of a thumbnail
+const THUMB_SIDE: u32 = 480;
+
+/// images wider or taller than this aren't decoded at all
+const MAX_INPUT_SIDE: u32 = 16384;
+
+/// frames of an animation, and their pixels taken together, beyond which
+/// it isn't re-encoded but refused
+const MAX_FRAMES: usize = 500;
+const MAX_ANIMATION_PIXELS: u64 = 100_000_000;
+
+/// a pasted or dropped image, ready to be attached
+pub struct Upload {
+    /// "3f2a...c1", from the uploaded bytes, so the same image is stored once
+    pub stem: String,
+    /// "png", "jpg" or "gif"
+    pub ext: &'static str,
+    pub bytes: Vec<u8>,
+    /// (ext, bytes), for images bigger than a thumbnail
+    pub thumbnail: Option<(&'static str, Vec<u8>)>,
+}
+
+impl Upload {
+    pub fn name(&self) -> String {
+        format!("{}.{}", self.stem, self.ext)
+    }
+
+    pub fn thumbnail_name(&self) -> Option<String> {
+        let (ext, _) = self.thumbnail.as_ref()?;
+        Some(format!("{}.thumb.{ext}", self.stem))
+    }
+}
+
+/// decode, orient, scale down and re-encode an image
+///
+/// re-encoding keeps the pixels only, so EXIF (camera, location, ...) and other
+/// metadata is gone; gifs are re-encoded frame by frame to keep their animation
+pub fn process(bytes: &[u8]) -> Result<Upload> {
+    use sha3::{Digest, Sha3_256};
+    let hash = Sha3_256::digest(bytes);
+    let stem: String = hash[..8].iter().map(|b| format!("{b:02x}")).collect();
+
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|_| Ex::InvalidImage)?;
+    let format = reader.format().ok_or(Ex::InvalidImage)?;
+
+    // the first frame of a gif stands in for its thumbnail
+    let (image, (ext, bytes)) = match format {
+        ImageFormat::Gif => {
+            let (first, gif) = encode_gif(bytes)?;
+            (first, ("gif", gif))
+        }
+        ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::WebP => {
+            reader.limits(limits());
+            let mut decoder = reader.into_decoder().map_err(|_| Ex::InvalidImage)?;
+            let orientation = decoder.orientation().map_err(|_| Ex::InvalidImage)?;
+            let mut image = DynamicImage::from_decoder(decoder).map_err(|_| Ex::InvalidImage)?;
+            image.apply_orientation(orientation);
+            if image.width().max(image.height()) > MAX_SIDE {
+                image = image.resize(MAX_SIDE, MAX_SIDE, image::imageops::FilterType::Lanczos3);
+            }
+            let encoded = encode(&image, format)?;
+            (image, encoded)
+        }
+        _ => return Err(Ex::InvalidImage),
+    };
+    let thumbnail = match image.width().max(image.height()) > THUMB_SIDE {
+        true => Some(encode(&image.thumbnail(THUMB_SIDE, THUMB_SIDE), format)?),
+        false => None,
+    };
+    Ok(Upload {
+        stem,
+        ext,
+        bytes,
+        thumbnail,
+    })
+}
+
+fn limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_INPUT_SIDE);
+    limits.max_image_height = Some(MAX_INPUT_SIDE);
+    limits
+}
+
+/// every frame scaled down like a still image, one frame in memory at a time,
+/// and the first of them
+fn encode_gif(bytes: &[u8]) -> Result<(DynamicImage, Vec<u8>)> {
+    use image::AnimationDecoder;
+    use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+    let mut decoder = GifDecoder::new(Cursor::new(bytes)).map_err(|_| Ex::InvalidImage)?;
+    decoder.set_limits(limits()).map_err(|_| Ex::InvalidImage)?;
+    // frames are decoded onto the whole canvas
+    let (width, height) = decoder.dimensions();
+    let canvas = u64::from(width) * u64::from(height);
+    let mut first = None;
+    let mut out = vec![];
+    {
+        let mut encoder = GifEncoder::new(&mut out);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|_| Ex::InvalidImage)?;
+        for (n, frame) in decoder.into_frames().enumerate() {
+            if n >= MAX_FRAMES || (n as u64 + 1) * canvas > MAX_ANIMATION_PIXELS {
+                return Err(Ex::InvalidImage);
+            }
+            let frame = frame.map_err(|_| Ex::InvalidImage)?;
+            let delay = frame.delay();
+            let mut buffer = frame.into_buffer();
+            if buffer.width().max(buffer.height()) > MAX_SIDE {
+                buffer = DynamicImage::ImageRgba8(buffer)
+                    .resize(MAX_SIDE, MAX_SIDE, image::imageops::FilterType::Triangle)
+                    .into_rgba8();
+            }
+            if first.is_none() {
+                first = Some(DynamicImage::ImageRgba8(buffer.clone()));
+            }
+            encoder
+                .encode_frame(image::Frame::from_parts(buffer, 0, 0, delay))
+                .map_err(|_| Ex::InvalidImage)?;
+        }
+    }
+    Ok((first.ok_or(Ex::InvalidImage)?, out))
+}
+
+/// jpeg for photos, png for everything that is (or may be) transparent
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<(&'static str, Vec<u8>)> {
+    let mut buf = Cursor::new(vec![]);
+    let ext = match format {
+        ImageFormat::Jpeg | ImageFormat::WebP if !image.color().has_alpha() => {
+            let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buf, 85);
+            image
+                .to_rgb8()
+                .write_with_encoder(encoder)
+                .map_err(|_| Ex::InvalidImage)?;
+            "jpg"
+        }
+        _ => {
+            image
+                .write_to(&mut buf, ImageFormat::Png)
+                .map_err(|_| Ex::InvalidImage)?;
+            "png"
+        }
+    };
+    Ok((ext, buf.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn strips_and_scales() {
+        // a jpeg with an EXIF block (APP1) right after the start of image
+        let mut jpeg = vec![];
+        let photo = RgbImage::from_pixel(4096, 100, Rgb([200, 100, 50]));
+        let encoder = image::codecs::jpeg::JpegEncoder::new(&mut jpeg);
+        photo.write_with_encoder(encoder).unwrap();
+        let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\0secret-gps";
+        let mut app1 = vec![0xff, 0xe1];
+        app1.extend(((exif.len() + 2) as u16).to_be_bytes());
+        app1.extend(exif);
+        jpeg.splice(2..2, app1);
+
+        let upload = process(&jpeg).unwrap();
+        assert_eq!(upload.ext, "jpg");
+        assert!(!upload.bytes.windows(10).any(|w| w == b"secret-gps"));
+        let image = image::load_from_memory(&upload.bytes).unwrap();
+        assert_eq!((image.width(), image.height()), (2048, 50));
+        let (ext, thumbnail) = upload.thumbnail.as_ref().unwrap();
+        let thumbnail = image::load_from_memory(thumbnail).unwrap();
+        assert_eq!((*ext, thumbnail.width()), ("jpg", 480));
+        assert_eq!(upload.name(), format!("{}.jpg", upload.stem));
+
+        // small, no thumbnail
+        let mut png = vec![];
+        let icon = RgbImage::from_pixel(16, 16, Rgb([0, 0, 0]));
+        icon.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let upload = process(&png).unwrap();
+        assert_eq!(upload.ext, "png");
+        assert!(upload.thumbnail_name().is_none());
+
+        assert!(matches!(process(b"not an image"), Err(Ex::InvalidImage)));
+    }
+
+    #[test]
+    fn gifs_are_reencoded() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Delay, Frame, Rgba, RgbaImage};
+
+        // two frames, and a comment extension before the trailer
+        let mut gif = vec![];
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            for color in [[255, 0, 0, 255], [0, 0, 255, 255]] {
+                let buffer = RgbaImage::from_pixel(3000, 10, Rgba(color));
+                let delay = Delay::from_numer_denom_ms(100, 1);
+                encoder
+                    .encode_frame(Frame::from_parts(buffer, 0, 0, delay))
+                    .unwrap();
+            }
+        }
+        let trailer = gif.len() - 1;
+        gif.splice(trailer..trailer, *b"\x21\xfe\x0asecret-gps\x00");
+
+        let upload = process(&gif).unwrap();
+        assert_eq!(upload.ext, "gif");
+        assert!(!upload.bytes.windows(10).any(|w| w == b"secret-gps"));
+        let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(&upload.bytes)).unwrap();
+        assert_eq!(decoder.dimensions(), (2048, 7));
+        use image::AnimationDecoder;
+        assert_eq!(decoder.into_frames().count(), 2);
+        assert!(upload.thumbnail.is_some());
+    }
+
+    /// a gif of `frames` single pixels on a `width` x `height` canvas
+    fn animation(width: u16, height: u16, frames: usize) -> Vec<u8> {
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend(width.to_le_bytes());
+        gif.extend(height.to_le_bytes());
+        gif.extend([0, 0, 0]);
+        for _ in 0..frames {
+            gif.extend([0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0x80]);
+            gif.extend([0, 0, 0, 255, 255, 255]);
+            gif.extend([2, 2, 0x44, 0x01, 0]);
+        }
+        gif.push(0x3b);
+        gif
+    }
+
+    #[test]
+    fn oversized_animations_are_refused() {
+        assert!(process(&animation(1, 1, 10)).is_ok());
+        for gif in [animation(1, 1, MAX_FRAMES + 1), animation(16384, 16384, 1)] {
+            assert!(matches!(process(&gif), Err(Ex::InvalidImage)));
+        }
+    }
+}
@@ -0,0 +1,370 @@
+use crate::models::attachments::Attachment;
+use crate::models::drafts::Draft;
+use crate::models::pages::{self, Page};
+use crate::models::store::Store;
+use crate::models::trash::Trashed;
+use crate::models::types::{Ex, Result};
+use crate::models::users::UserData;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+/// `Store` kept in memory, for tests
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    users: BTreeMap<String, UserData>,
+    pages: BTreeMap<(String, String), Page>,
+    aliases: BTreeMap<(String, String), (String, String)>,
+    // (user, file, deleted_at): (deleted_by, page)
+    trash: BTreeMap<(String, String, i64), (String, Page)>,
+    // (user, file, name), deleted_at is Some for pages in the trash
+    attachments: BTreeMap<(String, String, Option<i64>, String), Attachment>,
+    // (user, file, editor)
+    drafts: BTreeMap<(String, String, String), Draft>,
+}
+
+impl Data {
+    /// re-key the attachments of (user, file, deleted_at)
+    fn move_attachments(&mut self, from: (&str, &str, Option<i64>), to: (&str, &str, Option<i64>)) {
+        let keys: Vec<_> = self
+            .attachments
+            .keys()
+            .filter(|(u, f, d, _)| (u.as_str(), f.as_str(), *d) == from)
+            .cloned()
+            .collect();
+        for key in keys {
+            let attachment = self.attachments.remove(&key).unwrap();
+            let (user, file, deleted_at) = to;
+            let to = (user.to_string(), file.to_string(), deleted_at, key.3);
+            self.attachments.insert(to, attachment);
+        }
+    }
+}
+
+impl MemoryStore {
+    fn lock(&self) -> Result<MutexGuard<'_, Data>> {
+        self.data.lock().map_err(|_| Ex::InternalServerError)
+    }
+}
+
+fn key(user: &str, file: &str) -> (String, String) {
+    (user.to_string(), file.to_string())
+}
+
+impl Store for MemoryStore {
+    fn ready(&self) -> Result<()> {
+        self.lock().map(drop)
+    }
+
+    // users
+
+    fn user(&self, user: &str) -> Result<Option<UserData>> {
+        Ok(self.lock()?.users.get(user).cloned())
+    }
+
+    fn create_user(&self, user: &str, mut data: UserData, inviter: Option<&str>) -> Result<()> {
+        let mut guard = self.lock()?;
+        if guard.users.contains_key(user) {
+            return Err(Ex::UserExists);
+        }
+        if let Some(inviter) = inviter {
+            let inviter_data = guard.users.get_mut(inviter).ok_or(Ex::InvalidInvite)?;
+            inviter_data.collabs.insert(user.to_string());
+            data.collabs.insert(inviter.to_string());
+        }
+        guard.users.insert(user.to_string(), data);
+        Ok(())
+    }
+
+    fn link_collab(&self, inviter: &str, user: &str) -> Result<()> {
+        let mut guard = self.lock()?;
+        let inviter_data = guard.users.get_mut(inviter).ok_or(Ex::InvalidInvite)?;
+        inviter_data.collabs.insert(user.to_string());
+        Ok(())
+    }
+
+    // pages
+
+    fn page(&self, user: &str, file: &str) -> Result<Option<Page>> {
+        Ok(self.lock()?.pages.get(&key(user, file)).cloned())
+    }
+
+    fn next_page(&self, user: &str, file: &str) -> Result<Option<(String, String, String)>> {
+        let guard = self.lock()?;
+        let current = key(user, file);
+        let next = guard
+            .pages
+            .range(current.clone()..)
+            .take_while(|((u, _), _)| u == user)
+            .find(|(k, _)| **k != current);
+        Ok(next.map(|((user, file), page)| (user.clone(), file.clone(), page.title.clone())))
+    }
+
+    fn pages(&self) -> Result<Vec<(String, String, Page)>> {
+        let guard = self.lock()?;
+        let pages = guard.pages.iter();
+        Ok(pages
+            .map(|((u, f), p)| (u.clone(), f.clone(), p.clone()))
+            .collect())
+    }
+
+    fn create_page(&self, auth: &str, user: &str, file: &str) -> Result<()> {
+        let mut guard = self.lock()?;
+        let Data { users, pages, .. } = &mut *guard;
+        let target_data = users.get_mut(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        if pages.contains_key(&key(user, file)) {
+            return Err(Ex::PageAlreadyExists);
+        }
+        target_data.files.insert(file.to_string());
+        pages.insert(key(user, file), Page::new("Untitled", ""));
+        Ok(())
+    }
+
+    fn update_page(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        title: &str,
+        markdown: &str,
+    ) -> Result<()> {
+        let mut guard = self.lock()?;
+        let Data { users, pages, .. } = &mut *guard;
+        let target_data = users.get_mut(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        let page = pages.get_mut(&key(user, file)).ok_or(Ex::PageNotFound)?;
+        *page = Page::new(title, markdown);
+        target_data.files.insert(file.to_string());
+        let draft = (user.to_string(), file.to_string(), auth.to_string());
+        guard.drafts.remove(&draft);
+        Ok(())
+    }
+
+    fn delete_page(&self, auth: &str, user: &str, file: &str) -> Result<()> {
+        let mut guard = self.lock()?;
+        let Data { users, pages, .. } = &mut *guard;
+        let target_data = users.get_mut(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        let page = pages.remove(&key(user, file)).ok_or(Ex::PageNotFound)?;
+        target_data.files.remove(file);
+        let mut trashed = (user.to_string(), file.to_string(), 0);
+        trashed.2 = time::UtcDateTime::now().unix_timestamp();
+        while guard.trash.contains_key(&trashed) {
+            trashed.2 += 1;
+        }
+        let deleted_at = trashed.2;
+        guard.trash.insert(trashed, (auth.to_string(), page));
+        guard.move_attachments((user, file, None), (user, file, Some(deleted_at)));
+        guard
+            .drafts
+            .retain(|(u, f, _), _| (u.as_str(), f.as_str()) != (user, file));
+        Ok(())
+    }
+
+    fn trash(&self, user: &str) -> Result<Vec<Trashed>> {
+        let guard = self.lock()?;
+        let mut trash: Vec<Trashed> = guard
+            .trash
+            .iter()
+            .filter(|((u, _, _), _)| u == user)
+            .map(|((_, file, deleted_at), (deleted_by, page))| Trashed {
+                file: file.clone(),
+                deleted_at: *deleted_at,
+                deleted_by: deleted_by.clone(),
+                page: page.clone(),
+            })
+            .collect();
+        trash.sort_by(|a, b| (b.deleted_at, &b.file).cmp(&(a.deleted_at, &a.file)));
+        Ok(trash)
+    }
+
+    fn restore_page(&self, auth: &str, user: &str, file: &str, deleted_at: i64) -> Result<()> {
+        let mut guard = self.lock()?;
+        let Data {
+            users,
+            pages,
+            trash,
+            ..
+        } = &mut *guard;
+        let target_data = users.get_mut(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        if pages.contains_key(&key(user, file)) {
+            return Err(Ex::PageAlreadyExists);
+        }
+        let trashed = (user.to_string(), file.to_string(), deleted_at);
+        let (_, page) = trash.remove(&trashed).ok_or(Ex::PageNotFound)?;
+        target_data.files.insert(file.to_string());
+        pages.insert(key(user, file), page);
+        guard.move_attachments((user, file, Some(deleted_at)), (user, file, None));
+        Ok(())
+    }
+
+    fn purge_page(&self, auth: &str, user: &str, file: &str, deleted_at: i64) -> Result<()> {
+        let mut guard = self.lock()?;
+        let target_data = guard.users.get(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        let trashed = (user.to_string(), file.to_string(), deleted_at);
+        guard.trash.remove(&trashed).ok_or(Ex::PageNotFound)?;
+        guard.attachments.retain(|(u, f, d, _), _| {
+            (u.as_str(), f.as_str(), *d) != (user, file, Some(deleted_at))
+        });
+        Ok(())
+    }
+
+    fn rename_page(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        to_user: &str,
+        to_file: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut guard = self.lock()?;
+        let Data {
+            users,
+            pages: pages_map,
+            aliases,
+            ..
+        } = &mut *guard;
+        let source_data = users.get(user).ok_or(Ex::UserNotFound)?;
+        source_data.check_edit(auth, user)?;
+        users
+            .get(to_user)
+            .ok_or(Ex::UserNotFound)?
+            .check_edit(auth, to_user)?;
+
+        // the page and everything under it, not into itself
+        let moves = pages::subtree_moves(&source_data.files, file, to_file);
+        if moves.is_empty() {
+            return Err(Ex::PageNotFound);
+        }
+        if to_user == user && pages::is_under(to_file, file) {
+            return Err(Ex::PageAlreadyExists);
+        }
+        if moves
+            .iter()
+            .any(|(_, to)| pages_map.contains_key(&key(to_user, to)))
+        {
+            return Err(Ex::PageAlreadyExists);
+        }
+
+        for (from, to) in &moves {
+            let (from, to) = (key(user, from), key(to_user, to));
+            let page = pages_map.remove(&from).ok_or(Ex::PageNotFound)?;
+            pages_map.insert(to.clone(), page);
+            users.get_mut(user).unwrap().files.remove(&from.1);
+            users.get_mut(to_user).unwrap().files.insert(to.1.clone());
+
+            // older aliases follow the page, none points at a page
+            aliases.remove(&to);
+            for target in aliases.values_mut().filter(|target| **target == from) {
+                *target = to.clone();
+            }
+            aliases.insert(from, to);
+        }
+        for (from, to) in &moves {
+            guard.move_attachments((user, from, None), (to_user, to, None));
+            let keys: Vec<_> = guard
+                .drafts
+                .keys()
+                .filter(|(u, f, _)| (u.as_str(), f.as_str()) == (user, from.as_str()))
+                .cloned()
+                .collect();
+            for key in keys {
+                let draft = guard.drafts.remove(&key).unwrap();
+                guard
+                    .drafts
+                    .insert((to_user.to_string(), to.clone(), key.2), draft);
+            }
+        }
+        Ok(moves)
+    }
+
+    fn alias(&self, user: &str, file: &str) -> Result<Option<(String, String)>> {
+        Ok(self.lock()?.aliases.get(&key(user, file)).cloned())
+    }
+
+    fn rerender_page(&self, user: &str, file: &str) -> Result<Option<Page>> {
+        let mut guard = self.lock()?;
+        let Some(page) = guard.pages.get_mut(&key(user, file)) else {
+            return Ok(None);
+        };
+        page.rerender();
+        Ok(Some(page.clone()))
+    }
+
+    // attachments
+
+    fn attachment(&self, user: &str, file: &str, name: &str) -> Result<Option<Attachment>> {
+        let key = (user.to_string(), file.to_string(), None, name.to_string());
+        Ok(self.lock()?.attachments.get(&key).cloned())
+    }
+
+    fn attachments(&self, user: &str, file: &str) -> Result<Vec<(String, String, usize)>> {
+        let guard = self.lock()?;
+        let attachments = guard
+            .attachments
+            .iter()
+            .filter(|((u, f, d, _), _)| (u.as_str(), f.as_str(), *d) == (user, file, None))
+            .map(|((_, _, _, name), a)| (name.clone(), a.mime.clone(), a.bytes.len()));
+        Ok(attachments.collect())
+    }
+
+    fn attach(&self, auth: &str, user: &str, file: &str, name: &str, bytes: &[u8]) -> Result<()> {
+        let mut guard = self.lock()?;
+        let target_data = guard.users.get(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        if !guard.pages.contains_key(&key(user, file)) {
+            return Err(Ex::PageNotFound);
+        }
+        let key = (user.to_string(), file.to_string(), None, name.to_string());
+        guard.attachments.insert(key, Attachment::new(bytes));
+        Ok(())
+    }
+
+    fn detach(&self, auth: &str, user: &str, file: &str, name: &str) -> Result<()> {
+        let mut guard = self.lock()?;
+        let target_data = guard.users.get(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        let key = (user.to_string(), file.to_string(), None, name.to_string());
+        guard.attachments.remove(&key).ok_or(Ex::PageNotFound)?;
+        Ok(())
+    }
+
+    // drafts
+
+    fn draft(&self, user: &str, file: &str, editor: &str) -> Result<Option<Draft>> {
+        let key = (user.to_string(), file.to_string(), editor.to_string());
+        Ok(self.lock()?.drafts.get(&key).cloned())
+    }
+
+    fn save_draft(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        title: &str,
+        markdown: &str,
+    ) -> Result<()> {
+        let mut guard = self.lock()?;
+        let target_data = guard.users.get(user).ok_or(Ex::UserNotFound)?;
+        target_data.check_edit(auth, user)?;
+        if !guard.pages.contains_key(&key(user, file)) {
+            return Err(Ex::PageNotFound);
+        }
+        let key = (user.to_string(), file.to_string(), auth.to_string());
+        guard.drafts.insert(key, Draft::new(title, markdown));
+        Ok(())
+    }
+
+    fn discard_draft(&self, auth: &str, user: &str, file: &str) -> Result<()> {
+        let key = (user.to_string(), file.to_string(), auth.to_string());
+        self.lock()?.drafts.remove(&key);
+        Ok(())
+    }
+}
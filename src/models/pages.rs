@@ -1,8 +1,31 @@
-use redb::TableDefinition;
+use crate::models::front_matter::{self, FrontMatter};
+use crate::models::schema;
+use crate::models::types::Result;
+use base64::prelude::*;
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
 
 /// (user, file): PageData
 pub const PAGES: TableDefinition<(&str, &str), PageData> = TableDefinition::new("pages");
 
+/// (date, user, file): (), index of `PAGES` by date, kept in step by every write
+pub const PAGES_BY_DATE: TableDefinition<(i64, &str, &str), ()> =
+    TableDefinition::new("pages_by_date");
+
+/// (tag, user, file): (), pages by the tags in their front matter
+pub const TAGS: TableDefinition<(&str, &str, &str), ()> = TableDefinition::new("tags");
+
+/// (user, series, order, file): (), pages of a series in reading order
+pub const SERIES: TableDefinition<(&str, &str, i64, &str), ()> = TableDefinition::new("series");
+
+/// (user, file): (user, file), where a renamed page lives now
+pub const ALIASES: TableDefinition<(&str, &str), (&str, &str)> = TableDefinition::new("aliases");
+
+/// bump whenever `PageData::render` output changes (options, extensions, ...)
+pub const RENDERER_VERSION: u32 = 2;
+
 // no fine-grained modification needed, so ownership doesn't matter
 #[derive(Debug)]
 pub struct PageData<'a> {
@@ -10,21 +33,33 @@ pub struct PageData<'a> {
     pub markdown: &'a str,
     pub html: &'a str,
     pub date: i64,
+    // RENDERER_VERSION that produced `html`
+    pub renderer: u32,
 }
 
 impl<'a> PageData<'a> {
     pub fn new(title: &'a str, markdown: &'a str, buf: &'a mut String) -> Self {
-        // parse
-        let parser = pulldown_cmark::Parser::new_ext(&markdown, pulldown_cmark::Options::all());
-        pulldown_cmark::html::push_html(buf, parser);
-
+        Self::render(markdown, buf);
         Self {
             title,
             markdown,
             html: buf.as_str(),
             date: time::UtcDateTime::now().unix_timestamp(),
+            renderer: RENDERER_VERSION,
         }
     }
+
+    /// `html` was rendered by an older pipeline
+    pub fn is_stale(&self) -> bool {
+        self.renderer != RENDERER_VERSION
+    }
+
+    /// markdown -> html, appended to `buf`, without the front matter
+    pub fn render(markdown: &str, buf: &mut String) {
+        let (_, markdown) = front_matter::split(markdown);
+        let parser = pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::all());
+        pulldown_cmark::html::push_html(buf, parser);
+    }
 }
 
 impl<'a> redb::Value for PageData<'a> {
@@ -45,13 +80,16 @@ impl<'a> redb::Value for PageData<'a> {
     where
         Self: 'b,
     {
-        let (title, markdown, html, date) =
-            <(&str, &str, &str, i64) as redb::Value>::from_bytes(data);
+        let (title, markdown, html, date, renderer) = match schema::decode(data) {
+            (2, payload) => <(&str, &str, &str, i64, u32) as redb::Value>::from_bytes(payload),
+            (version, _) => panic!("unsupported PageData encoding v{version}"),
+        };
         PageData {
             title,
             markdown,
             html,
             date,
+            renderer,
         }
     }
 
@@ -59,15 +97,291 @@ impl<'a> redb::Value for PageData<'a> {
     where
         Self: 'c,
     {
-        <(&str, &str, &str, i64) as redb::Value>::as_bytes(&(
+        let payload = <(&str, &str, &str, i64, u32) as redb::Value>::as_bytes(&(
             value.title,
             value.markdown,
             value.html,
             value.date,
-        ))
+            value.renderer,
+        ));
+        schema::encode(2, &payload)
     }
 
     fn type_name() -> redb::TypeName {
         redb::TypeName::new("PageData")
     }
 }
+
+/// owned page, as handed out by a `Store`
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub title: String,
+    pub markdown: String,
+    pub html: String,
+    pub date: i64,
+    pub renderer: u32,
+}
+
+impl Page {
+    pub fn new(title: &str, markdown: &str) -> Self {
+        PageData::new(title, markdown, &mut String::new()).into()
+    }
+
+    pub fn as_data(&self) -> PageData<'_> {
+        PageData {
+            title: &self.title,
+            markdown: &self.markdown,
+            html: &self.html,
+            date: self.date,
+            renderer: self.renderer,
+        }
+    }
+
+    /// `html` was rendered by an older pipeline
+    pub fn is_stale(&self) -> bool {
+        self.renderer != RENDERER_VERSION
+    }
+
+    /// tags, summary, ... from the top of the markdown
+    pub fn front_matter(&self) -> FrontMatter {
+        front_matter::split(&self.markdown).0.unwrap_or_default()
+    }
+
+    /// render `markdown` again with the current pipeline, keeping the date
+    pub fn rerender(&mut self) {
+        self.html.clear();
+        PageData::render(&self.markdown, &mut self.html);
+        self.renderer = RENDERER_VERSION;
+    }
+}
+
+impl From<PageData<'_>> for Page {
+    fn from(data: PageData<'_>) -> Self {
+        Page {
+            title: data.title.to_string(),
+            markdown: data.markdown.to_string(),
+            html: data.html.to_string(),
+            date: data.date,
+            renderer: data.renderer,
+        }
+    }
+}
+
+/// `path` is `folder` or somewhere under it
+pub fn is_under(path: &str, folder: &str) -> bool {
+    path.strip_prefix(folder)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// (from, to) for `file` and every page under it, moved to `to_file`
+pub fn subtree_moves<'a>(
+    files: impl IntoIterator<Item = &'a String>,
+    file: &str,
+    to_file: &str,
+) -> Vec<(String, String)> {
+    files
+        .into_iter()
+        .filter(|f| is_under(f, file))
+        .map(|f| (f.clone(), format!("{to_file}{}", &f[file.len()..])))
+        .collect()
+}
+
+/// (folders, pages) right under `folder` ("" for the top), as full paths
+///
+/// a path can be both, when a page has pages under it
+pub fn children<'a>(files: &'a BTreeSet<String>, folder: &str) -> (Vec<String>, Vec<&'a str>) {
+    let prefix = match folder {
+        "" => String::new(),
+        folder => format!("{folder}/"),
+    };
+    let (mut folders, mut pages) = (Vec::<String>::new(), vec![]);
+    for file in files.range(prefix.clone()..) {
+        let Some(rest) = file.strip_prefix(&prefix) else {
+            break;
+        };
+        match rest.split_once('/') {
+            // sorted, so a folder's pages are next to each other
+            Some((name, _)) if folders.last().is_none_or(|f| f[prefix.len()..] != *name) => {
+                folders.push(format!("{prefix}{name}"));
+            }
+            Some(_) => {}
+            None => pages.push(file.as_str()),
+        }
+    }
+    (folders, pages)
+}
+
+/// re-render every stale page, keeping dates, return how many were updated
+pub fn rerender_all(db: &Database) -> Result<usize> {
+    let write_txn = db.begin_write()?;
+    let count = {
+        let mut pages_table = write_txn.open_table(PAGES)?;
+        let mut stale = vec![];
+        for entry in pages_table.iter()? {
+            let (key, page) = entry?;
+            let page = page.value();
+            if page.is_stale() {
+                let (user, file) = key.value();
+                stale.push(((user.to_string(), file.to_string()), Page::from(page)));
+            }
+        }
+
+        for ((user, file), page) in &mut stale {
+            page.rerender();
+            pages_table.insert((user.as_str(), file.as_str()), page.as_data())?;
+        }
+        stale.len()
+    };
+    write_txn.commit()?;
+    println!("Re-rendered {count} pages");
+    Ok(count)
+}
+
+/// order of page listings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sort {
+    /// last modified first
+    #[default]
+    Date,
+    Title,
+    Author,
+}
+
+impl Sort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Sort::Date => "date",
+            Sort::Title => "title",
+            Sort::Author => "author",
+        }
+    }
+
+    /// `a` comes before `b` in this order
+    pub fn cmp(self, a: &Cursor, b: &Cursor) -> Ordering {
+        let key = |c: &'_ Cursor| (c.user.clone(), c.file.clone());
+        match self {
+            Sort::Date => (b.date, key(b)).cmp(&(a.date, key(a))),
+            Sort::Title => (a.title.to_lowercase(), &a.title, key(a)).cmp(&(
+                b.title.to_lowercase(),
+                &b.title,
+                key(b),
+            )),
+            Sort::Author => key(a).cmp(&key(b)),
+        }
+    }
+}
+
+/// position in a listing, the last row already shown
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    pub date: i64,
+    pub title: String,
+    pub user: String,
+    pub file: String,
+}
+
+impl Cursor {
+    pub fn new(user: &str, file: &str, page: &Page) -> Self {
+        Self {
+            date: page.date,
+            title: page.title.clone(),
+            user: user.to_string(),
+            file: file.to_string(),
+        }
+    }
+
+    /// opaque, url-safe form
+    pub fn encode(&self) -> String {
+        let Cursor {
+            date,
+            title,
+            user,
+            file,
+        } = self;
+        BASE64_URL_SAFE_NO_PAD.encode(format!("{date}\n{user}\n{file}\n{title}"))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let bytes = BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let text = String::from_utf8(bytes).ok()?;
+        let mut parts = text.splitn(4, '\n');
+        Some(Self {
+            date: parts.next()?.parse().ok()?,
+            user: parts.next()?.to_string(),
+            file: parts.next()?.to_string(),
+            title: parts.next()?.to_string(),
+        })
+    }
+}
+
+/// sort `rows` and keep `limit` of them after `after`, for listings without an index
+pub fn paginate(
+    rows: Vec<(String, String, Page)>,
+    sort: Sort,
+    after: Option<&Cursor>,
+    limit: usize,
+) -> Vec<(String, String, Page)> {
+    let mut rows: Vec<_> = rows
+        .into_iter()
+        .map(|(user, file, page)| (Cursor::new(&user, &file, &page), (user, file, page)))
+        .filter(|(cursor, _)| after.is_none_or(|after| sort.cmp(after, cursor).is_lt()))
+        .collect();
+    rows.sort_by(|(a, _), (b, _)| sort.cmp(a, b));
+    rows.into_iter().take(limit).map(|(_, row)| row).collect()
+}
+
+/// `TAGS` and `SERIES`, the indexes built from front matter, open for a write
+pub struct FrontMatterIndex<'txn> {
+    tags: redb::Table<'txn, (&'static str, &'static str, &'static str), ()>,
+    series: redb::Table<'txn, (&'static str, &'static str, i64, &'static str), ()>,
+}
+
+impl<'txn> FrontMatterIndex<'txn> {
+    pub fn open(txn: &'txn redb::WriteTransaction) -> Result<Self> {
+        Ok(Self {
+            tags: txn.open_table(TAGS)?,
+            series: txn.open_table(SERIES)?,
+        })
+    }
+
+    /// add (`insert`) or drop the rows of a page with this markdown
+    pub fn update(&mut self, user: &str, file: &str, markdown: &str, insert: bool) -> Result<()> {
+        let Some(front_matter) = front_matter::split(markdown).0 else {
+            return Ok(());
+        };
+        for tag in &front_matter.tags {
+            match insert {
+                true => self.tags.insert((tag.as_str(), user, file), ())?,
+                false => self.tags.remove((tag.as_str(), user, file))?,
+            };
+        }
+        if let Some((series, order)) = front_matter.series_key() {
+            match insert {
+                true => self
+                    .series
+                    .insert((user, series.as_str(), order, file), ())?,
+                false => self.series.remove((user, series.as_str(), order, file))?,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// rebuild `PAGES_BY_DATE`, `TAGS` and `SERIES` from `PAGES`
+pub fn reindex(txn: &redb::WriteTransaction) -> Result<()> {
+    txn.delete_table(PAGES_BY_DATE)?;
+    txn.delete_table(TAGS)?;
+    txn.delete_table(SERIES)?;
+    let pages_table = txn.open_table(PAGES)?;
+    let mut index = txn.open_table(PAGES_BY_DATE)?;
+    let mut front_matter = FrontMatterIndex::open(txn)?;
+    for entry in pages_table.iter()? {
+        let (key, page) = entry?;
+        let (user, file) = key.value();
+        let page = page.value();
+        index.insert((page.date, user, file), ())?;
+        front_matter.update(user, file, page.markdown, true)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,705 @@
+use crate::models::attachments::{self, ATTACHMENTS, Attachment};
+use crate::models::drafts::{self, DRAFTS, Draft};
+use crate::models::pages::{
+    self, ALIASES, Cursor, FrontMatterIndex, PAGES, PAGES_BY_DATE, Page, PageData, SERIES, Sort,
+    TAGS,
+};
+use crate::models::store::Store;
+use crate::models::trash::{TRASH, Trashed};
+use crate::models::types::{Ex, Result};
+use crate::models::users::{USERS, UserData};
+use redb::{Database, ReadableDatabase, ReadableTable};
+use std::sync::Arc;
+
+/// `Store` backed by a redb file
+pub struct RedbStore {
+    db: Arc<Database>,
+}
+
+impl RedbStore {
+    pub fn new(db: Arc<Database>) -> Self {
+        Self { db }
+    }
+}
+
+impl Store for RedbStore {
+    fn ready(&self) -> Result<()> {
+        self.db.begin_read()?;
+        Ok(())
+    }
+
+    fn redb(&self) -> Option<Arc<Database>> {
+        Some(self.db.clone())
+    }
+
+    // users
+
+    fn user(&self, user: &str) -> Result<Option<UserData>> {
+        let read_txn = self.db.begin_read()?;
+        let users_table = match read_txn.open_table(USERS) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(users_table.get(user)?.map(|guard| guard.value()))
+    }
+
+    fn create_user(&self, user: &str, mut data: UserData, inviter: Option<&str>) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut users_table = write_txn.open_table(USERS)?;
+
+            // check user exists
+            if users_table.get(user)?.is_some() {
+                return Err(Ex::UserExists);
+            }
+
+            // connect node
+            if let Some(inviter) = inviter {
+                let mut inviter_entry = users_table.get_mut(inviter)?.ok_or(Ex::InvalidInvite)?;
+                let mut inviter_data = inviter_entry.value().clone();
+                inviter_data.collabs.insert(user.to_string());
+                inviter_entry.insert(inviter_data)?;
+                data.collabs.insert(inviter.to_string());
+            }
+
+            users_table.insert(user, data)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn link_collab(&self, inviter: &str, user: &str) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut users_table = write_txn.open_table(USERS)?;
+            let mut inviter_entry = users_table.get_mut(inviter)?.ok_or(Ex::InvalidInvite)?;
+            let mut inviter_data = inviter_entry.value().clone();
+            inviter_data.collabs.insert(user.to_string());
+            inviter_entry.insert(&inviter_data)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    // pages
+
+    fn page(&self, user: &str, file: &str) -> Result<Option<Page>> {
+        let read_txn = self.db.begin_read()?;
+        let pages_table = match read_txn.open_table(PAGES) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(pages_table
+            .get((user, file))?
+            .map(|guard| guard.value().into()))
+    }
+
+    fn next_page(&self, user: &str, file: &str) -> Result<Option<(String, String, String)>> {
+        let read_txn = self.db.begin_read()?;
+        let pages_table = read_txn.open_table(PAGES)?;
+        let mut page_iter = pages_table.range((user, file)..)?;
+        let next_page = page_iter
+            .find(|entry| entry.as_ref().is_ok_and(|(k, _)| k.value() != (user, file)))
+            .transpose()?
+            .filter(|(k, _)| k.value().0 == user);
+        Ok(next_page.map(|(k, v)| {
+            let (user, file) = k.value();
+            (
+                user.to_string(),
+                file.to_string(),
+                v.value().title.to_string(),
+            )
+        }))
+    }
+
+    fn pages(&self) -> Result<Vec<(String, String, Page)>> {
+        let read_txn = self.db.begin_read()?;
+        let Ok(pages_table) = read_txn.open_table(PAGES) else {
+            return Ok(vec![]);
+        };
+        let pages = pages_table
+            .iter()?
+            .filter_map(|result| {
+                result.ok().map(|(key, value)| {
+                    let (user, file) = key.value();
+                    (user.into(), file.into(), value.value().into())
+                })
+            })
+            .collect();
+        Ok(pages)
+    }
+
+    fn list_pages(
+        &self,
+        sort: Sort,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Page)>> {
+        let read_txn = self.db.begin_read()?;
+        let pages_table = match read_txn.open_table(PAGES) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        // (user, file) in order, from the index or the primary key
+        let mut keys: Vec<(String, String)> = vec![];
+        match sort {
+            Sort::Date => {
+                let index = read_txn.open_table(PAGES_BY_DATE)?;
+                let range = match after {
+                    Some(c) => index.range(..(c.date, c.user.as_str(), c.file.as_str()))?,
+                    None => index.range::<(i64, &str, &str)>(..)?,
+                };
+                for entry in range.rev().take(limit) {
+                    let (key, _) = entry?;
+                    let (_, user, file) = key.value();
+                    keys.push((user.to_string(), file.to_string()));
+                }
+            }
+            Sort::Author => {
+                let range = match after {
+                    Some(c) => pages_table.range((c.user.as_str(), c.file.as_str())..)?,
+                    None => pages_table.range::<(&str, &str)>(..)?,
+                };
+                for entry in range {
+                    let (key, _) = entry?;
+                    let (user, file) = key.value();
+                    let key = (user.to_string(), file.to_string());
+                    if after.is_some_and(|c| (&c.user, &c.file) == (&key.0, &key.1)) {
+                        continue;
+                    }
+                    keys.push(key);
+                    if keys.len() == limit {
+                        break;
+                    }
+                }
+            }
+            // no index on titles, sort them all
+            Sort::Title => return Ok(pages::paginate(self.pages()?, sort, after, limit)),
+        }
+
+        let mut rows = vec![];
+        for (user, file) in keys {
+            if let Some(page) = pages_table.get((user.as_str(), file.as_str()))? {
+                let page = page.value().into();
+                rows.push((user, file, page));
+            }
+        }
+        Ok(rows)
+    }
+
+    fn user_pages(&self, user: &str, limit: usize) -> Result<Vec<(String, Page)>> {
+        let read_txn = self.db.begin_read()?;
+        let pages_table = match read_txn.open_table(PAGES) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let index = read_txn.open_table(PAGES_BY_DATE)?;
+
+        // newest first from the index, pages are only read for the ones kept
+        let mut files = vec![];
+        for entry in index.range::<(i64, &str, &str)>(..)?.rev() {
+            let (key, _) = entry?;
+            let (_, page_user, file) = key.value();
+            if page_user == user {
+                files.push(file.to_string());
+                if files.len() == limit {
+                    break;
+                }
+            }
+        }
+
+        let mut rows = vec![];
+        for file in files {
+            if let Some(page) = pages_table.get((user, file.as_str()))? {
+                rows.push((file, page.value().into()));
+            }
+        }
+        Ok(rows)
+    }
+
+    fn tags(&self) -> Result<Vec<(String, usize)>> {
+        let read_txn = self.db.begin_read()?;
+        let tags_table = match read_txn.open_table(TAGS) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut tags: Vec<(String, usize)> = vec![];
+        for entry in tags_table.iter()? {
+            let (key, _) = entry?;
+            let (tag, _, _) = key.value();
+            match tags.last_mut() {
+                Some((last, count)) if last == tag => *count += 1,
+                _ => tags.push((tag.to_string(), 1)),
+            }
+        }
+        Ok(tags)
+    }
+
+    fn tagged(&self, tag: &str) -> Result<Vec<(String, String, Page)>> {
+        let read_txn = self.db.begin_read()?;
+        let (tags_table, pages_table) =
+            match (read_txn.open_table(TAGS), read_txn.open_table(PAGES)) {
+                (Ok(tags), Ok(pages)) => (tags, pages),
+                (Err(redb::TableError::TableDoesNotExist(_)), _)
+                | (_, Err(redb::TableError::TableDoesNotExist(_))) => return Ok(vec![]),
+                (Err(e), _) | (_, Err(e)) => return Err(e.into()),
+            };
+        let mut rows = vec![];
+        for entry in tags_table.range((tag, "", "")..)? {
+            let (key, _) = entry?;
+            let (entry_tag, user, file) = key.value();
+            if entry_tag != tag {
+                break;
+            }
+            if let Some(page) = pages_table.get((user, file))? {
+                rows.push((user.to_string(), file.to_string(), page.value().into()));
+            }
+        }
+        rows.sort_by(|a: &(String, String, Page), b| {
+            (b.2.date, &a.0, &a.1).cmp(&(a.2.date, &b.0, &b.1))
+        });
+        Ok(rows)
+    }
+
+    fn series(&self, user: &str, series: &str) -> Result<Vec<(String, Page)>> {
+        let read_txn = self.db.begin_read()?;
+        let (series_table, pages_table) =
+            match (read_txn.open_table(SERIES), read_txn.open_table(PAGES)) {
+                (Ok(series), Ok(pages)) => (series, pages),
+                (Err(redb::TableError::TableDoesNotExist(_)), _)
+                | (_, Err(redb::TableError::TableDoesNotExist(_))) => return Ok(vec![]),
+                (Err(e), _) | (_, Err(e)) => return Err(e.into()),
+            };
+        let mut rows = vec![];
+        for entry in series_table.range((user, series, i64::MIN, "")..)? {
+            let (key, _) = entry?;
+            let (entry_user, entry_series, _, file) = key.value();
+            if (entry_user, entry_series) != (user, series) {
+                break;
+            }
+            if let Some(page) = pages_table.get((user, file))? {
+                rows.push((file.to_string(), page.value().into()));
+            }
+        }
+        Ok(rows)
+    }
+
+    fn create_page(&self, auth: &str, user: &str, file: &str) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut users_table = write_txn.open_table(USERS)?;
+            let mut pages_table = write_txn.open_table(PAGES)?;
+
+            // target user
+            let mut target_entry = users_table.get_mut(user)?.ok_or(Ex::UserNotFound)?;
+            let mut target_data = target_entry.value().clone();
+            target_data.check_edit(auth, user)?;
+
+            // check if page already exists
+            if pages_table.get((user, file))?.is_some() {
+                return Err(Ex::PageAlreadyExists);
+            }
+
+            // create new page
+            target_data.files.insert(file.to_string());
+            target_entry.insert(target_data)?;
+            let mut buf = String::new();
+            let page = PageData::new("Untitled", "", &mut buf);
+            write_txn
+                .open_table(PAGES_BY_DATE)?
+                .insert((page.date, user, file), ())?;
+            pages_table.insert((user, file), page)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn update_page(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        title: &str,
+        markdown: &str,
+    ) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut users_table = write_txn.open_table(USERS)?;
+            let mut pages_table = write_txn.open_table(PAGES)?;
+
+            // target user
+            let mut target_entry = users_table.get_mut(user)?.ok_or(Ex::UserNotFound)?;
+            let mut target_data = target_entry.value().clone();
+            target_data.check_edit(auth, user)?;
+
+            // update file
+            let mut page_entry = pages_table.get_mut((user, file))?.ok_or(Ex::PageNotFound)?;
+            let old = Page::from(page_entry.value());
+            let mut buf = String::new();
+            let page = PageData::new(title, markdown, &mut buf);
+            let mut index = write_txn.open_table(PAGES_BY_DATE)?;
+            index.remove((old.date, user, file))?;
+            index.insert((page.date, user, file), ())?;
+            let mut front_matter = FrontMatterIndex::open(&write_txn)?;
+            front_matter.update(user, file, &old.markdown, false)?;
+            front_matter.update(user, file, markdown, true)?;
+            page_entry.insert(page)?;
+            target_data.files.insert(file.to_string());
+            target_entry.insert(target_data)?;
+
+            // published, the editor's draft is done
+            write_txn.open_table(DRAFTS)?.remove((user, file, auth))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn delete_page(&self, auth: &str, user: &str, file: &str) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut users_table = write_txn.open_table(USERS)?;
+            let mut pages_table = write_txn.open_table(PAGES)?;
+
+            // target user
+            let mut target_entry = users_table.get_mut(user)?.ok_or(Ex::UserNotFound)?;
+            let mut target_data = target_entry.value().clone();
+            target_data.check_edit(auth, user)?;
+
+            // move to the trash
+            let page = pages_table
+                .remove((user, file))?
+                .map(|g| Page::from(g.value()))
+                .ok_or(Ex::PageNotFound)?;
+            target_data.files.remove(file);
+            target_entry.insert(target_data)?;
+            write_txn
+                .open_table(PAGES_BY_DATE)?
+                .remove((page.date, user, file))?;
+            FrontMatterIndex::open(&write_txn)?.update(user, file, &page.markdown, false)?;
+            // seconds, a path deleted again within one takes the next free second
+            let mut trash = write_txn.open_table(TRASH)?;
+            let mut deleted_at = time::UtcDateTime::now().unix_timestamp();
+            while trash.get((user, file, deleted_at))?.is_some() {
+                deleted_at += 1;
+            }
+            trash.insert((user, file, deleted_at), (auth, page.as_data()))?;
+            drop(trash);
+            attachments::trash(&write_txn, user, file, deleted_at)?;
+            drafts::take(&write_txn, user, file)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn trash(&self, user: &str) -> Result<Vec<Trashed>> {
+        let read_txn = self.db.begin_read()?;
+        let trash_table = match read_txn.open_table(TRASH) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut trash = vec![];
+        for entry in trash_table.range((user, "", i64::MIN)..)? {
+            let (key, value) = entry?;
+            let (entry_user, file, deleted_at) = key.value();
+            if entry_user != user {
+                break;
+            }
+            let (deleted_by, page) = value.value();
+            trash.push(Trashed {
+                file: file.to_string(),
+                deleted_at,
+                deleted_by: deleted_by.to_string(),
+                page: page.into(),
+            });
+        }
+        trash.sort_by(|a, b| (b.deleted_at, &b.file).cmp(&(a.deleted_at, &a.file)));
+        Ok(trash)
+    }
+
+    fn restore_page(&self, auth: &str, user: &str, file: &str, deleted_at: i64) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut users_table = write_txn.open_table(USERS)?;
+            let mut pages_table = write_txn.open_table(PAGES)?;
+            let mut trash_table = write_txn.open_table(TRASH)?;
+
+            // target user
+            let mut target_entry = users_table.get_mut(user)?.ok_or(Ex::UserNotFound)?;
+            let mut target_data = target_entry.value().clone();
+            target_data.check_edit(auth, user)?;
+
+            // the name may have been taken since
+            if pages_table.get((user, file))?.is_some() {
+                return Err(Ex::PageAlreadyExists);
+            }
+
+            // restore
+            let page = trash_table
+                .remove((user, file, deleted_at))?
+                .map(|g| Page::from(g.value().1))
+                .ok_or(Ex::PageNotFound)?;
+            write_txn
+                .open_table(PAGES_BY_DATE)?
+                .insert((page.date, user, file), ())?;
+            FrontMatterIndex::open(&write_txn)?.update(user, file, &page.markdown, true)?;
+            pages_table.insert((user, file), page.as_data())?;
+            target_data.files.insert(file.to_string());
+            target_entry.insert(target_data)?;
+            attachments::restore(&write_txn, user, file, deleted_at)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn purge_page(&self, auth: &str, user: &str, file: &str, deleted_at: i64) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let users_table = write_txn.open_table(USERS)?;
+            let target_data = users_table.get(user)?.ok_or(Ex::UserNotFound)?.value();
+            target_data.check_edit(auth, user)?;
+            write_txn
+                .open_table(TRASH)?
+                .remove((user, file, deleted_at))?
+                .ok_or(Ex::PageNotFound)?;
+            attachments::purge(&write_txn, user, file, deleted_at)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn rename_page(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        to_user: &str,
+        to_file: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let write_txn = self.db.begin_write()?;
+        let moves = {
+            let mut users_table = write_txn.open_table(USERS)?;
+            let mut pages_table = write_txn.open_table(PAGES)?;
+
+            // both namespaces
+            let mut source_data = users_table.get(user)?.ok_or(Ex::UserNotFound)?.value();
+            source_data.check_edit(auth, user)?;
+            let mut target_data = users_table.get(to_user)?.ok_or(Ex::UserNotFound)?.value();
+            target_data.check_edit(auth, to_user)?;
+
+            // the page and everything under it, not into itself
+            let moves = pages::subtree_moves(&source_data.files, file, to_file);
+            if moves.is_empty() {
+                return Err(Ex::PageNotFound);
+            }
+            if to_user == user && pages::is_under(to_file, file) {
+                return Err(Ex::PageAlreadyExists);
+            }
+            for (_, to) in &moves {
+                if pages_table.get((to_user, to.as_str()))?.is_some() {
+                    return Err(Ex::PageAlreadyExists);
+                }
+            }
+
+            // move the pages, keeping their dates
+            let mut index = write_txn.open_table(PAGES_BY_DATE)?;
+            let mut front_matter = FrontMatterIndex::open(&write_txn)?;
+            for (from, to) in &moves {
+                let (from, to) = (from.as_str(), to.as_str());
+                let page = pages_table
+                    .remove((user, from))?
+                    .map(|g| Page::from(g.value()))
+                    .ok_or(Ex::PageNotFound)?;
+                pages_table.insert((to_user, to), page.as_data())?;
+                index.remove((page.date, user, from))?;
+                index.insert((page.date, to_user, to), ())?;
+                front_matter.update(user, from, &page.markdown, false)?;
+                front_matter.update(to_user, to, &page.markdown, true)?;
+                attachments::rename(&write_txn, user, from, to_user, to)?;
+                drafts::rename(&write_txn, user, from, to_user, to)?;
+
+                // same user: one record, both changes
+                source_data.files.remove(from);
+                match to_user == user {
+                    true => source_data.files.insert(to.to_string()),
+                    false => target_data.files.insert(to.to_string()),
+                };
+            }
+            if to_user != user {
+                users_table.insert(to_user, target_data)?;
+            }
+            users_table.insert(user, source_data)?;
+
+            // older aliases follow their pages, none points at a page
+            let mut aliases = write_txn.open_table(ALIASES)?;
+            let mut moved = vec![];
+            for entry in aliases.iter()? {
+                let (alias, target) = entry?;
+                let (target_user, target_file) = target.value();
+                let to = moves.iter().find(|(from, _)| from == target_file);
+                if let Some((_, to)) = to.filter(|_| target_user == user) {
+                    let (alias_user, alias_file) = alias.value();
+                    moved.push((alias_user.to_string(), alias_file.to_string(), to.clone()));
+                }
+            }
+            for (alias_user, alias_file, to) in &moved {
+                let alias = (alias_user.as_str(), alias_file.as_str());
+                aliases.insert(alias, (to_user, to.as_str()))?;
+            }
+            for (from, to) in &moves {
+                aliases.remove((to_user, to.as_str()))?;
+                aliases.insert((user, from.as_str()), (to_user, to.as_str()))?;
+            }
+            moves
+        };
+        write_txn.commit()?;
+        Ok(moves)
+    }
+
+    fn alias(&self, user: &str, file: &str) -> Result<Option<(String, String)>> {
+        let read_txn = self.db.begin_read()?;
+        let aliases = match read_txn.open_table(ALIASES) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(aliases.get((user, file))?.map(|guard| {
+            let (user, file) = guard.value();
+            (user.to_string(), file.to_string())
+        }))
+    }
+
+    fn rerender_page(&self, user: &str, file: &str) -> Result<Option<Page>> {
+        let write_txn = self.db.begin_write()?;
+        let page = {
+            let mut pages_table = write_txn.open_table(PAGES)?;
+            let Some(mut page) = pages_table
+                .get((user, file))?
+                .map(|g| Page::from(g.value()))
+            else {
+                return Ok(None);
+            };
+            page.rerender();
+            pages_table.insert((user, file), page.as_data())?;
+            page
+        };
+        write_txn.commit()?;
+        Ok(Some(page))
+    }
+
+    // attachments
+
+    fn attachment(&self, user: &str, file: &str, name: &str) -> Result<Option<Attachment>> {
+        let read_txn = self.db.begin_read()?;
+        let attachments_table = match read_txn.open_table(ATTACHMENTS) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(attachments_table
+            .get((user, file, name))?
+            .map(|guard| guard.value().into()))
+    }
+
+    fn attachments(&self, user: &str, file: &str) -> Result<Vec<(String, String, usize)>> {
+        let read_txn = self.db.begin_read()?;
+        let attachments_table = match read_txn.open_table(ATTACHMENTS) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut attachments = vec![];
+        for entry in attachments_table.range((user, file, "")..)? {
+            let (key, value) = entry?;
+            let (entry_user, entry_file, name) = key.value();
+            if (entry_user, entry_file) != (user, file) {
+                break;
+            }
+            let (mime, _, bytes) = value.value();
+            attachments.push((name.to_string(), mime.to_string(), bytes.len()));
+        }
+        Ok(attachments)
+    }
+
+    fn attach(&self, auth: &str, user: &str, file: &str, name: &str, bytes: &[u8]) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let users_table = write_txn.open_table(USERS)?;
+            let target_data = users_table.get(user)?.ok_or(Ex::UserNotFound)?.value();
+            target_data.check_edit(auth, user)?;
+            if write_txn.open_table(PAGES)?.get((user, file))?.is_none() {
+                return Err(Ex::PageNotFound);
+            }
+            attachments::insert(&write_txn, user, file, name, &Attachment::new(bytes))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn detach(&self, auth: &str, user: &str, file: &str, name: &str) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let users_table = write_txn.open_table(USERS)?;
+            let target_data = users_table.get(user)?.ok_or(Ex::UserNotFound)?.value();
+            target_data.check_edit(auth, user)?;
+            write_txn
+                .open_table(ATTACHMENTS)?
+                .remove((user, file, name))?
+                .ok_or(Ex::PageNotFound)?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    // drafts
+
+    fn draft(&self, user: &str, file: &str, editor: &str) -> Result<Option<Draft>> {
+        let read_txn = self.db.begin_read()?;
+        let drafts_table = match read_txn.open_table(DRAFTS) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(drafts_table
+            .get((user, file, editor))?
+            .map(|guard| guard.value().into()))
+    }
+
+    fn save_draft(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        title: &str,
+        markdown: &str,
+    ) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let users_table = write_txn.open_table(USERS)?;
+            let target_data = users_table.get(user)?.ok_or(Ex::UserNotFound)?.value();
+            target_data.check_edit(auth, user)?;
+            if write_txn.open_table(PAGES)?.get((user, file))?.is_none() {
+                return Err(Ex::PageNotFound);
+            }
+            let saved_at = time::UtcDateTime::now().unix_timestamp();
+            write_txn
+                .open_table(DRAFTS)?
+                .insert((user, file, auth), (title, markdown, saved_at))?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    fn discard_draft(&self, auth: &str, user: &str, file: &str) -> Result<()> {
+        let write_txn = self.db.begin_write()?;
+        write_txn.open_table(DRAFTS)?.remove((user, file, auth))?;
+        write_txn.commit()?;
+        Ok(())
+    }
+}
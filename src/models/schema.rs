@@ -0,0 +1,404 @@
+use crate::models::pages::{self, PAGES_BY_DATE, SERIES, TAGS};
+use crate::models::types::{Ex, Result};
+use redb::{Database, ReadableTable, TableDefinition, TableHandle, TypeName, WriteTransaction};
+
+/// key: value, holds "schema_version"
+pub const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+/// version written by this build, bump together with a new entry in `MIGRATIONS`
+pub const SCHEMA_VERSION: u64 = 5;
+
+/// a schema upgrade, run with every migration newer than the stored version
+///
+/// migrations are frozen once released: they only read the encodings of their
+/// own version, and index tables are filled by `pages::reindex` after the last
+/// one, so they hold what this build would write
+pub struct Migration {
+    pub version: u64,
+    pub name: &'static str,
+    pub run: fn(&WriteTransaction) -> Result<()>,
+    /// adds an index table, filled once all migrations ran
+    pub reindex: bool,
+}
+
+/// ordered by version, each one moves the schema from `version - 1` to `version`
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "prefix values with an encoding version",
+        run: v1_versioned_encoding,
+        reindex: false,
+    },
+    Migration {
+        version: 2,
+        name: "record the renderer version of pages",
+        run: v2_page_renderer,
+        reindex: false,
+    },
+    Migration {
+        version: 3,
+        name: "index pages by date",
+        run: v3_pages_by_date,
+        reindex: true,
+    },
+    Migration {
+        version: 4,
+        name: "index pages by tag",
+        run: v4_tags,
+        reindex: true,
+    },
+    Migration {
+        version: 5,
+        name: "index pages by series",
+        run: v5_series,
+        reindex: true,
+    },
+];
+
+/// bring the database up to `SCHEMA_VERSION`, return the version found
+///
+/// all pending migrations share one write transaction, so a failure leaves the
+/// database exactly as it was
+pub fn migrate(db: &Database) -> Result<u64> {
+    let write_txn = db.begin_write()?;
+    let found = stored_version(&write_txn)?;
+    if found > SCHEMA_VERSION {
+        return Err(Ex::UnsupportedSchemaVersion);
+    }
+
+    let mut reindex = false;
+    for migration in MIGRATIONS.iter().filter(|m| m.version > found) {
+        (migration.run)(&write_txn)?;
+        reindex |= migration.reindex;
+        println!(
+            "Migrated schema to v{}: {}",
+            migration.version, migration.name
+        );
+    }
+    if reindex {
+        pages::reindex(&write_txn)?;
+    }
+
+    write_txn
+        .open_table(META)?
+        .insert("schema_version", SCHEMA_VERSION)?;
+    write_txn.commit()?;
+    Ok(found)
+}
+
+/// schema version of the database, databases from before versioning are v0
+pub fn stored_version(txn: &WriteTransaction) -> Result<u64> {
+    let mut tables = txn.list_tables()?.map(|t| t.name().to_string());
+    let Some(_) = tables.find(|name| name == META.name() || name == "users" || name == "pages")
+    else {
+        // fresh database, nothing to migrate
+        return Ok(SCHEMA_VERSION);
+    };
+
+    let meta = txn.open_table(META)?;
+    let version = meta.get("schema_version")?.map_or(0, |v| v.value());
+    Ok(version)
+}
+
+// versioned encoding
+
+/// prepend the encoding version to a serialized value
+pub fn encode(version: u8, payload: &[u8]) -> Vec<u8> {
+    [&[version], payload].concat()
+}
+
+/// split a serialized value into its encoding version and payload
+pub fn decode(data: &[u8]) -> (u8, &[u8]) {
+    match data.split_first() {
+        Some((version, payload)) => (*version, payload),
+        None => (0, data),
+    }
+}
+
+/// the payload of a value a migration expects at encoding `version`
+fn decode_exactly(version: u8, data: &[u8]) -> Result<&[u8]> {
+    match decode(data) {
+        (found, payload) if found == version => Ok(payload),
+        _ => Err(Ex::DataEncodingError),
+    }
+}
+
+/// untyped value, lets migrations rewrite bytes without knowing the old types
+macro_rules! raw_value {
+    ($name:ident, $type_name:literal) => {
+        #[derive(Debug)]
+        pub struct $name;
+
+        impl redb::Value for $name {
+            type SelfType<'a> = &'a [u8];
+            type AsBytes<'a> = &'a [u8];
+
+            fn fixed_width() -> Option<usize> {
+                None
+            }
+
+            fn from_bytes<'a>(data: &'a [u8]) -> &'a [u8]
+            where
+                Self: 'a,
+            {
+                data
+            }
+
+            fn as_bytes<'a, 'b: 'a>(value: &'a &'b [u8]) -> &'a [u8]
+            where
+                Self: 'b,
+            {
+                value
+            }
+
+            fn type_name() -> TypeName {
+                TypeName::new($type_name)
+            }
+        }
+    };
+}
+
+raw_value!(RawUserData, "UserData");
+raw_value!(RawPageData, "PageData");
+
+const RAW_USERS: TableDefinition<&str, RawUserData> = TableDefinition::new("users");
+const RAW_PAGES: TableDefinition<(&str, &str), RawPageData> = TableDefinition::new("pages");
+
+// migrations
+
+/// v0 stored bare tuples, v1 is the same tuple behind a version byte
+fn v1_versioned_encoding(txn: &WriteTransaction) -> Result<()> {
+    rewrite_users(txn, |value| encode(1, value))?;
+    rewrite_pages(txn, |value| Ok(encode(1, value)))
+}
+
+/// pages gain the renderer version, 0 marks html from before it was tracked
+fn v2_page_renderer(txn: &WriteTransaction) -> Result<()> {
+    rewrite_pages(txn, |value| {
+        let (title, markdown, html, date) =
+            <(&str, &str, &str, i64) as redb::Value>::from_bytes(decode_exactly(1, value)?);
+        let payload = <(&str, &str, &str, i64, u32) as redb::Value>::as_bytes(&(
+            title, markdown, html, date, 0,
+        ));
+        Ok(encode(2, &payload))
+    })
+}
+
+/// `PAGES_BY_DATE`, filled from the dates stored in pages
+fn v3_pages_by_date(txn: &WriteTransaction) -> Result<()> {
+    txn.open_table(PAGES_BY_DATE)?;
+    Ok(())
+}
+
+/// `TAGS`, filled from the front matter of pages
+fn v4_tags(txn: &WriteTransaction) -> Result<()> {
+    txn.open_table(TAGS)?;
+    Ok(())
+}
+
+/// `SERIES`, filled from the front matter of pages
+fn v5_series(txn: &WriteTransaction) -> Result<()> {
+    txn.open_table(SERIES)?;
+    Ok(())
+}
+
+fn rewrite_users(txn: &WriteTransaction, f: impl Fn(&[u8]) -> Vec<u8>) -> Result<()> {
+    let mut users = txn.open_table(RAW_USERS)?;
+    let rows: Vec<(String, Vec<u8>)> = users
+        .iter()?
+        .map(|entry| {
+            let (key, value) = entry?;
+            Ok((key.value().to_string(), f(value.value())))
+        })
+        .collect::<std::result::Result<_, redb::StorageError>>()?;
+    for (user, value) in rows {
+        users.insert(user.as_str(), value.as_slice())?;
+    }
+    Ok(())
+}
+
+fn rewrite_pages(txn: &WriteTransaction, f: impl Fn(&[u8]) -> Result<Vec<u8>>) -> Result<()> {
+    let mut pages = txn.open_table(RAW_PAGES)?;
+    let rows: Vec<((String, String), Vec<u8>)> = pages
+        .iter()?
+        .map(|entry| {
+            let (key, value) = entry?;
+            let (user, file) = key.value();
+            Ok(((user.to_string(), file.to_string()), f(value.value())?))
+        })
+        .collect::<Result<_>>()?;
+    for ((user, file), value) in rows {
+        pages.insert((user.as_str(), file.as_str()), value.as_slice())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::pages::PAGES;
+    use crate::models::users::USERS;
+    use redb::backends::InMemoryBackend;
+    use redb::{ReadableDatabase, Value};
+
+    fn empty_db() -> Database {
+        Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap()
+    }
+
+    /// a database as written before schema versioning (v0)
+    fn v0_fixture() -> Database {
+        let db = empty_db();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut users = write_txn.open_table(RAW_USERS).unwrap();
+            let alice = <([u8; 32], Vec<&str>, Vec<&str>) as Value>::as_bytes(&(
+                [7; 32],
+                vec!["bob"],
+                vec!["hello", "todo"],
+            ));
+            let bob = <([u8; 32], Vec<&str>, Vec<&str>) as Value>::as_bytes(&(
+                [9; 32],
+                vec!["alice"],
+                vec![],
+            ));
+            users.insert("alice", alice.as_slice()).unwrap();
+            users.insert("bob", bob.as_slice()).unwrap();
+
+            let mut pages = write_txn.open_table(RAW_PAGES).unwrap();
+            let hello = <(&str, &str, &str, i64) as Value>::as_bytes(&(
+                "Hello",
+                "---\ntags: [greeting]\n---\n# hi",
+                "<h1>hi</h1>\n",
+                1_700_000_000,
+            ));
+            let todo =
+                <(&str, &str, &str, i64) as Value>::as_bytes(&("Untitled", "", "", 1_700_000_001));
+            pages.insert(("alice", "hello"), hello.as_slice()).unwrap();
+            pages.insert(("alice", "todo"), todo.as_slice()).unwrap();
+        }
+        write_txn.commit().unwrap();
+        db
+    }
+
+    fn version(db: &Database) -> u64 {
+        let write_txn = db.begin_write().unwrap();
+        stored_version(&write_txn).unwrap()
+    }
+
+    #[test]
+    fn fresh_database_starts_at_current_version() {
+        let db = empty_db();
+        assert_eq!(migrate(&db).unwrap(), SCHEMA_VERSION);
+        assert_eq!(version(&db), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn v0_database_is_migrated() {
+        let db = v0_fixture();
+        assert_eq!(version(&db), 0);
+        assert_eq!(migrate(&db).unwrap(), 0);
+        assert_eq!(version(&db), SCHEMA_VERSION);
+
+        let read_txn = db.begin_read().unwrap();
+        let users = read_txn.open_table(USERS).unwrap();
+        let alice = users.get("alice").unwrap().unwrap().value();
+        assert!(alice.collabs.contains("bob"));
+        assert_eq!(alice.files.len(), 2);
+        let bob = users.get("bob").unwrap().unwrap().value();
+        assert!(bob.collabs.contains("alice"));
+        assert!(bob.files.is_empty());
+
+        let pages = read_txn.open_table(PAGES).unwrap();
+        let hello = pages.get(("alice", "hello")).unwrap().unwrap();
+        let hello = hello.value();
+        assert_eq!(hello.title, "Hello");
+        assert!(hello.markdown.ends_with("# hi"));
+        assert_eq!(hello.date, 1_700_000_000);
+        assert!(hello.is_stale());
+
+        let index = read_txn.open_table(PAGES_BY_DATE).unwrap();
+        let dates: Vec<(i64, String)> = index
+            .iter()
+            .unwrap()
+            .map(|entry| {
+                let (key, _) = entry.unwrap();
+                let (date, _, file) = key.value();
+                (date, file.to_string())
+            })
+            .collect();
+        assert_eq!(
+            dates,
+            vec![
+                (1_700_000_000, "hello".to_string()),
+                (1_700_000_001, "todo".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn v0_tags_are_indexed() {
+        let db = v0_fixture();
+        migrate(&db).unwrap();
+
+        let read_txn = db.begin_read().unwrap();
+        let tags = read_txn.open_table(TAGS).unwrap();
+        let rows: Vec<(String, String)> = tags
+            .iter()
+            .unwrap()
+            .map(|entry| {
+                let (key, _) = entry.unwrap();
+                let (tag, _, file) = key.value();
+                (tag.to_string(), file.to_string())
+            })
+            .collect();
+        assert_eq!(rows, [("greeting".to_string(), "hello".to_string())]);
+    }
+
+    #[test]
+    fn migration_is_idempotent() {
+        let db = v0_fixture();
+        migrate(&db).unwrap();
+        assert_eq!(migrate(&db).unwrap(), SCHEMA_VERSION);
+
+        let read_txn = db.begin_read().unwrap();
+        let pages = read_txn.open_table(PAGES).unwrap();
+        assert_eq!(
+            pages.get(("alice", "todo")).unwrap().unwrap().value().date,
+            1_700_000_001
+        );
+    }
+
+    #[test]
+    fn unexpected_encoding_is_rejected() {
+        // stamped v1, with a page already in the v2 encoding
+        let db = empty_db();
+        let write_txn = db.begin_write().unwrap();
+        {
+            let mut meta = write_txn.open_table(META).unwrap();
+            meta.insert("schema_version", 1).unwrap();
+            let mut pages = write_txn.open_table(RAW_PAGES).unwrap();
+            let page = <(&str, &str, &str, i64, u32) as Value>::as_bytes(&("A", "", "", 0, 0));
+            pages
+                .insert(("alice", "a"), encode(2, &page).as_slice())
+                .unwrap();
+        }
+        write_txn.commit().unwrap();
+        assert!(matches!(migrate(&db), Err(Ex::DataEncodingError)));
+        assert_eq!(version(&db), 1);
+    }
+
+    #[test]
+    fn newer_database_is_rejected() {
+        let db = empty_db();
+        let write_txn = db.begin_write().unwrap();
+        write_txn
+            .open_table(META)
+            .unwrap()
+            .insert("schema_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        write_txn.commit().unwrap();
+        assert!(matches!(migrate(&db), Err(Ex::UnsupportedSchemaVersion)));
+    }
+}
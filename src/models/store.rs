@@ -0,0 +1,704 @@
+use crate::models::attachments::Attachment;
+use crate::models::drafts::Draft;
+use crate::models::pages::{self, Cursor, Page, Sort};
+use crate::models::trash::Trashed;
+use crate::models::types::{Ex, Result};
+use crate::models::users::UserData;
+use redb::Database;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// user and page storage, handlers only talk to this
+///
+/// writes are atomic and check permissions themselves (`auth` is the signed-in
+/// user), so a handler can't forget a check or race against another request
+pub trait Store: Send + Sync {
+    /// storage is usable (readiness probe)
+    fn ready(&self) -> Result<()>;
+
+    /// the underlying redb database, for maintenance (backup, fsck, ...)
+    fn redb(&self) -> Option<Arc<Database>> {
+        None
+    }
+
+    // users
+
+    fn user(&self, user: &str) -> Result<Option<UserData>>;
+
+    /// add a new user, collaborating both ways with `inviter` (if any)
+    fn create_user(&self, user: &str, data: UserData, inviter: Option<&str>) -> Result<()>;
+
+    /// let `user` edit `inviter`'s pages
+    fn link_collab(&self, inviter: &str, user: &str) -> Result<()>;
+
+    /// `auth` may edit `user`'s pages
+    fn check_edit(&self, auth: &str, user: &str) -> Result<()> {
+        let data = self.user(user)?.ok_or(Ex::UserNotFound)?;
+        data.check_edit(auth, user)
+    }
+
+    // pages
+
+    fn page(&self, user: &str, file: &str) -> Result<Option<Page>>;
+
+    /// (user, file, title) of `user`'s page after `file` in key order
+    fn next_page(&self, user: &str, file: &str) -> Result<Option<(String, String, String)>>;
+
+    /// (user, file, page) of every page
+    fn pages(&self) -> Result<Vec<(String, String, Page)>>;
+
+    /// (user, file, page) of up to `limit` pages in `sort` order, after `after`
+    fn list_pages(
+        &self,
+        sort: Sort,
+        after: Option<&Cursor>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, Page)>> {
+        Ok(pages::paginate(self.pages()?, sort, after, limit))
+    }
+
+    /// (file, page) of `user`'s newest `limit` pages, newest first
+    fn user_pages(&self, user: &str, limit: usize) -> Result<Vec<(String, Page)>> {
+        let mut rows: Vec<_> = self
+            .pages()?
+            .into_iter()
+            .filter(|(u, _, _)| u == user)
+            .map(|(_, file, page)| (file, page))
+            .collect();
+        rows.sort_by(|a, b| (b.1.date, &b.0).cmp(&(a.1.date, &a.0)));
+        rows.truncate(limit);
+        Ok(rows)
+    }
+
+    /// (tag, number of pages) of every tag, by name
+    fn tags(&self) -> Result<Vec<(String, usize)>> {
+        let mut counts = BTreeMap::new();
+        for (_, _, page) in self.pages()? {
+            for tag in page.front_matter().tags {
+                *counts.entry(tag).or_default() += 1;
+            }
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    /// (user, file, page) of the pages tagged `tag`, newest first
+    fn tagged(&self, tag: &str) -> Result<Vec<(String, String, Page)>> {
+        let mut rows: Vec<_> = self
+            .pages()?
+            .into_iter()
+            .filter(|(_, _, page)| page.front_matter().tags.iter().any(|t| t == tag))
+            .collect();
+        rows.sort_by(|a, b| (b.2.date, &a.0, &a.1).cmp(&(a.2.date, &b.0, &b.1)));
+        Ok(rows)
+    }
+
+    /// (file, page) of the pages in `user`'s series `series`, in reading order
+    fn series(&self, user: &str, series: &str) -> Result<Vec<(String, Page)>> {
+        let mut rows: Vec<_> = self
+            .pages()?
+            .into_iter()
+            .filter(|(u, _, _)| u == user)
+            .filter_map(|(_, file, page)| {
+                let (name, order) = page.front_matter().series_key()?;
+                (name == series).then_some((order, file, page))
+            })
+            .collect();
+        rows.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+        Ok(rows
+            .into_iter()
+            .map(|(_, file, page)| (file, page))
+            .collect())
+    }
+
+    fn create_page(&self, auth: &str, user: &str, file: &str) -> Result<()>;
+
+    fn update_page(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        title: &str,
+        markdown: &str,
+    ) -> Result<()>;
+
+    /// move (user, file) to the trash
+    fn delete_page(&self, auth: &str, user: &str, file: &str) -> Result<()>;
+
+    /// deleted pages of `user`, newest first
+    fn trash(&self, user: &str) -> Result<Vec<Trashed>>;
+
+    /// put a deleted page back where it was, with its old date
+    fn restore_page(&self, auth: &str, user: &str, file: &str, deleted_at: i64) -> Result<()>;
+
+    /// drop a deleted page for good
+    fn purge_page(&self, auth: &str, user: &str, file: &str, deleted_at: i64) -> Result<()>;
+
+    /// move (user, file) and the pages under it to (to_user, to_file), leaving
+    /// aliases behind, return the (from, to) files that moved
+    ///
+    /// `auth` must be able to edit both namespaces
+    fn rename_page(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        to_user: &str,
+        to_file: &str,
+    ) -> Result<Vec<(String, String)>>;
+
+    /// (user, file) a renamed page moved to
+    fn alias(&self, user: &str, file: &str) -> Result<Option<(String, String)>>;
+
+    /// store `page` re-rendered with the current pipeline, keeping its date
+    fn rerender_page(&self, user: &str, file: &str) -> Result<Option<Page>>;
+
+    // attachments, they follow their page into the trash and on renames
+
+    fn attachment(&self, user: &str, file: &str, name: &str) -> Result<Option<Attachment>>;
+
+    /// (name, mime, size) of the files attached to (user, file), by name
+    fn attachments(&self, user: &str, file: &str) -> Result<Vec<(String, String, usize)>>;
+
+    /// attach `bytes` to an existing page, replacing a file with the same name
+    fn attach(&self, auth: &str, user: &str, file: &str, name: &str, bytes: &[u8]) -> Result<()>;
+
+    fn detach(&self, auth: &str, user: &str, file: &str, name: &str) -> Result<()>;
+
+    // drafts, one per page and editor, dropped when that editor publishes
+
+    fn draft(&self, user: &str, file: &str, editor: &str) -> Result<Option<Draft>>;
+
+    /// keep `auth`'s unpublished edit of a page, the page itself is untouched
+    fn save_draft(
+        &self,
+        auth: &str,
+        user: &str,
+        file: &str,
+        title: &str,
+        markdown: &str,
+    ) -> Result<()>;
+
+    /// drop `auth`'s draft of a page, if any
+    fn discard_draft(&self, auth: &str, user: &str, file: &str) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::memory_store::MemoryStore;
+    use crate::models::redb_store::RedbStore;
+    use redb::backends::InMemoryBackend;
+
+    fn stores() -> Vec<Box<dyn Store>> {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        vec![
+            Box::new(MemoryStore::default()),
+            Box::new(RedbStore::new(Arc::new(db))),
+        ]
+    }
+
+    fn with_users(store: &dyn Store) {
+        store
+            .create_user("alice", UserData::default(), None)
+            .unwrap();
+        store
+            .create_user("bob", UserData::default(), Some("alice"))
+            .unwrap();
+        store.create_user("eve", UserData::default(), None).unwrap();
+    }
+
+    #[test]
+    fn users_and_collabs() {
+        for store in stores() {
+            with_users(store.as_ref());
+            assert!(matches!(
+                store.create_user("alice", UserData::default(), None),
+                Err(Ex::UserExists)
+            ));
+            assert!(matches!(
+                store.create_user("mallory", UserData::default(), Some("nobody")),
+                Err(Ex::InvalidInvite)
+            ));
+
+            let alice = store.user("alice").unwrap().unwrap();
+            assert!(alice.collabs.contains("bob"));
+            store.check_edit("bob", "alice").unwrap();
+            store.check_edit("alice", "bob").unwrap();
+            assert!(matches!(
+                store.check_edit("eve", "alice"),
+                Err(Ex::PermissionDenied)
+            ));
+
+            store.link_collab("eve", "alice").unwrap();
+            store.check_edit("alice", "eve").unwrap();
+            assert!(matches!(
+                store.check_edit("eve", "alice"),
+                Err(Ex::PermissionDenied)
+            ));
+        }
+    }
+
+    #[test]
+    fn page_lifecycle() {
+        for store in stores() {
+            with_users(store.as_ref());
+            store.create_page("alice", "alice", "a").unwrap();
+            store.create_page("bob", "alice", "b").unwrap();
+            assert!(matches!(
+                store.create_page("alice", "alice", "a"),
+                Err(Ex::PageAlreadyExists)
+            ));
+            assert!(matches!(
+                store.create_page("eve", "alice", "c"),
+                Err(Ex::PermissionDenied)
+            ));
+            assert!(matches!(
+                store.create_page("alice", "nobody", "c"),
+                Err(Ex::UserNotFound)
+            ));
+
+            store
+                .update_page("bob", "alice", "a", "A", "# heading")
+                .unwrap();
+            let page = store.page("alice", "a").unwrap().unwrap();
+            assert_eq!(page.title, "A");
+            assert!(page.html.contains("<h1>heading</h1>"));
+            assert!(matches!(
+                store.update_page("eve", "alice", "a", "x", "x"),
+                Err(Ex::PermissionDenied)
+            ));
+            assert!(matches!(
+                store.update_page("alice", "alice", "zzz", "x", "x"),
+                Err(Ex::PageNotFound)
+            ));
+
+            let next = store.next_page("alice", "a").unwrap();
+            assert_eq!(next.map(|n| n.1), Some("b".to_string()));
+            assert_eq!(store.pages().unwrap().len(), 2);
+
+            assert!(matches!(
+                store.delete_page("eve", "alice", "a"),
+                Err(Ex::PermissionDenied)
+            ));
+            store.delete_page("alice", "alice", "a").unwrap();
+            assert!(store.page("alice", "a").unwrap().is_none());
+            let alice = store.user("alice").unwrap().unwrap();
+            assert_eq!(Vec::from_iter(alice.files), vec!["b".to_string()]);
+        }
+    }
+
+    #[test]
+    fn rename() {
+        for store in stores() {
+            with_users(store.as_ref());
+            store.create_page("alice", "alice", "a").unwrap();
+            store.create_page("alice", "alice", "b").unwrap();
+            store.update_page("alice", "alice", "a", "A", "x").unwrap();
+            assert!(matches!(
+                store.rename_page("alice", "alice", "a", "alice", "b"),
+                Err(Ex::PageAlreadyExists)
+            ));
+            assert!(matches!(
+                store.rename_page("alice", "alice", "a", "eve", "a"),
+                Err(Ex::PermissionDenied)
+            ));
+            assert!(matches!(
+                store.rename_page("alice", "alice", "zzz", "alice", "c"),
+                Err(Ex::PageNotFound)
+            ));
+
+            // into a collaborator's namespace, then back under a new name
+            store
+                .rename_page("alice", "alice", "a", "bob", "a")
+                .unwrap();
+            store.rename_page("bob", "bob", "a", "alice", "c").unwrap();
+            assert_eq!(store.page("alice", "c").unwrap().unwrap().title, "A");
+            assert!(store.page("alice", "a").unwrap().is_none());
+            let files = |user| Vec::from_iter(store.user(user).unwrap().unwrap().files);
+            assert_eq!(files("alice"), ["b", "c"]);
+            assert!(files("bob").is_empty());
+            assert_eq!(store.list_pages(Sort::Date, None, 5).unwrap().len(), 2);
+
+            // every old name leads to the page
+            let alias = |user, file| store.alias(user, file).unwrap();
+            let target = Some(("alice".to_string(), "c".to_string()));
+            assert_eq!(alias("alice", "a"), target);
+            assert_eq!(alias("bob", "a"), target);
+            assert_eq!(alias("alice", "c"), None);
+        }
+    }
+
+    #[test]
+    fn rename_folder() {
+        for store in stores() {
+            with_users(store.as_ref());
+            for file in ["p", "p/a", "p/a/b", "p-q", "q"] {
+                store.create_page("alice", "alice", file).unwrap();
+            }
+            store
+                .rename_page("alice", "alice", "p/a", "alice", "r")
+                .unwrap();
+            assert!(matches!(
+                store.rename_page("alice", "alice", "p", "alice", "p/x"),
+                Err(Ex::PageAlreadyExists)
+            ));
+            assert!(matches!(
+                store.rename_page("alice", "alice", "p", "alice", "q"),
+                Err(Ex::PageAlreadyExists)
+            ));
+
+            // the whole tree, not its neighbours
+            let moves = store
+                .rename_page("alice", "alice", "p", "bob", "s")
+                .unwrap();
+            assert_eq!(moves, [("p".to_string(), "s".to_string())]);
+            let moves = store
+                .rename_page("alice", "alice", "r", "bob", "s/r")
+                .unwrap();
+            assert_eq!(moves.len(), 2);
+            let files = |user| Vec::from_iter(store.user(user).unwrap().unwrap().files);
+            assert_eq!(files("alice"), ["p-q", "q"]);
+            assert_eq!(files("bob"), ["s", "s/r", "s/r/b"]);
+            let target = Some(("bob".to_string(), "s/r/b".to_string()));
+            assert_eq!(store.alias("alice", "p/a/b").unwrap(), target);
+            assert_eq!(store.alias("alice", "r/b").unwrap(), target);
+        }
+    }
+
+    #[test]
+    fn trash_same_path_twice() {
+        for store in stores() {
+            with_users(store.as_ref());
+            for markdown in ["first", "second"] {
+                store.create_page("alice", "alice", "a").unwrap();
+                store
+                    .update_page("alice", "alice", "a", "A", markdown)
+                    .unwrap();
+                store
+                    .attach("alice", "alice", "a", markdown, markdown.as_bytes())
+                    .unwrap();
+                store.delete_page("alice", "alice", "a").unwrap();
+            }
+
+            // both kept, each with its own attachment
+            let trash = store.trash("alice").unwrap();
+            assert_eq!(trash.len(), 2);
+            assert_ne!(trash[0].deleted_at, trash[1].deleted_at);
+            for trashed in trash {
+                store
+                    .restore_page("alice", "alice", "a", trashed.deleted_at)
+                    .unwrap();
+                let markdown = store.page("alice", "a").unwrap().unwrap().markdown;
+                let names: Vec<_> = store
+                    .attachments("alice", "a")
+                    .unwrap()
+                    .into_iter()
+                    .map(|(name, _, _)| name)
+                    .collect();
+                assert_eq!(names, [markdown]);
+                store.delete_page("alice", "alice", "a").unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn trash() {
+        for store in stores() {
+            with_users(store.as_ref());
+            store.create_page("alice", "alice", "a").unwrap();
+            store.update_page("alice", "alice", "a", "A", "x").unwrap();
+            let date = store.page("alice", "a").unwrap().unwrap().date;
+            store.delete_page("bob", "alice", "a").unwrap();
+            assert!(store.page("alice", "a").unwrap().is_none());
+            assert!(store.list_pages(Sort::Date, None, 5).unwrap().is_empty());
+            assert!(matches!(
+                store.delete_page("alice", "alice", "a"),
+                Err(Ex::PageNotFound)
+            ));
+
+            let trash = store.trash("alice").unwrap();
+            assert_eq!(trash.len(), 1);
+            assert_eq!(
+                (trash[0].file.as_str(), trash[0].deleted_by.as_str()),
+                ("a", "bob")
+            );
+            assert!(store.trash("bob").unwrap().is_empty());
+            let deleted_at = trash[0].deleted_at;
+
+            // the name was taken again in the meantime
+            store.create_page("alice", "alice", "a").unwrap();
+            assert!(matches!(
+                store.restore_page("alice", "alice", "a", deleted_at),
+                Err(Ex::PageAlreadyExists)
+            ));
+            store
+                .rename_page("alice", "alice", "a", "alice", "b")
+                .unwrap();
+            assert!(matches!(
+                store.restore_page("eve", "alice", "a", deleted_at),
+                Err(Ex::PermissionDenied)
+            ));
+            store
+                .restore_page("alice", "alice", "a", deleted_at)
+                .unwrap();
+            let page = store.page("alice", "a").unwrap().unwrap();
+            assert_eq!((page.title.as_str(), page.date), ("A", date));
+            assert_eq!(store.list_pages(Sort::Date, None, 5).unwrap().len(), 2);
+            assert!(store.trash("alice").unwrap().is_empty());
+
+            store.delete_page("alice", "alice", "b").unwrap();
+            let deleted_at = store.trash("alice").unwrap()[0].deleted_at;
+            assert!(matches!(
+                store.purge_page("eve", "alice", "b", deleted_at),
+                Err(Ex::PermissionDenied)
+            ));
+            store.purge_page("alice", "alice", "b", deleted_at).unwrap();
+            assert!(store.trash("alice").unwrap().is_empty());
+            assert!(matches!(
+                store.restore_page("alice", "alice", "b", deleted_at),
+                Err(Ex::PageNotFound)
+            ));
+        }
+    }
+
+    #[test]
+    fn tags() {
+        for store in stores() {
+            with_users(store.as_ref());
+            for file in ["a", "b", "c"] {
+                store.create_page("alice", "alice", file).unwrap();
+            }
+            let tagged = "---\ntags: [Rust, web]\n---\nbody";
+            store
+                .update_page("alice", "alice", "a", "A", tagged)
+                .unwrap();
+            store
+                .update_page("alice", "alice", "b", "B", tagged)
+                .unwrap();
+            store
+                .update_page("alice", "alice", "c", "C", "+++\ntags = [\"web\"]\n+++\n")
+                .unwrap();
+            let page = store.page("alice", "a").unwrap().unwrap();
+            assert_eq!(page.html, "<p>body</p>\n");
+
+            // kept in step with updates, deletes, renames and restores
+            store
+                .update_page("alice", "alice", "b", "B", "untagged")
+                .unwrap();
+            store.delete_page("alice", "alice", "c").unwrap();
+            store
+                .rename_page("alice", "alice", "a", "bob", "a")
+                .unwrap();
+            let tags = |store: &dyn Store| store.tags().unwrap();
+            let files = |store: &dyn Store, tag| -> Vec<String> {
+                let rows = store.tagged(tag).unwrap();
+                rows.into_iter()
+                    .map(|(u, f, _)| format!("{u}/{f}"))
+                    .collect()
+            };
+            assert_eq!(
+                tags(store.as_ref()),
+                [("rust".to_string(), 1), ("web".to_string(), 1)]
+            );
+            assert_eq!(files(store.as_ref(), "web"), ["bob/a"]);
+
+            let deleted_at = store.trash("alice").unwrap()[0].deleted_at;
+            store
+                .restore_page("alice", "alice", "c", deleted_at)
+                .unwrap();
+            assert_eq!(files(store.as_ref(), "web").len(), 2);
+            assert!(files(store.as_ref(), "nothing").is_empty());
+        }
+    }
+
+    #[test]
+    fn series() {
+        for store in stores() {
+            with_users(store.as_ref());
+            for (file, order) in [("c", "1"), ("a", "2"), ("b", "")] {
+                let markdown = format!("---\nseries: Rust Book\norder: {order}\n---\n");
+                store.create_page("alice", "alice", file).unwrap();
+                store
+                    .update_page("alice", "alice", file, file, &markdown)
+                    .unwrap();
+            }
+            store.create_page("bob", "bob", "d").unwrap();
+            store
+                .update_page("bob", "bob", "d", "d", "---\nseries: rust book\n---\n")
+                .unwrap();
+            let files = |store: &dyn Store| -> Vec<String> {
+                let rows = store.series("alice", "rust-book").unwrap();
+                rows.into_iter().map(|(file, _)| file).collect()
+            };
+            // by order, pages without one last
+            assert_eq!(files(store.as_ref()), ["c", "a", "b"]);
+
+            store
+                .update_page("alice", "alice", "a", "a", "left the series")
+                .unwrap();
+            store
+                .rename_page("alice", "alice", "c", "alice", "e")
+                .unwrap();
+            assert_eq!(files(store.as_ref()), ["e", "b"]);
+            store.delete_page("alice", "alice", "b").unwrap();
+            assert_eq!(files(store.as_ref()), ["e"]);
+            assert!(store.series("alice", "nothing").unwrap().is_empty());
+
+            // next pages stay with their user
+            let next = store.next_page("alice", "e").unwrap();
+            assert!(next.is_none());
+        }
+    }
+
+    #[test]
+    fn attachments() {
+        for store in stores() {
+            with_users(store.as_ref());
+            store.create_page("alice", "alice", "a").unwrap();
+            let png = b"\x89PNG\r\n\x1a\n....";
+            assert!(matches!(
+                store.attach("eve", "alice", "a", "shot.png", png),
+                Err(Ex::PermissionDenied)
+            ));
+            assert!(matches!(
+                store.attach("alice", "alice", "nothing", "shot.png", png),
+                Err(Ex::PageNotFound)
+            ));
+            store.attach("bob", "alice", "a", "shot.png", png).unwrap();
+            store
+                .attach("alice", "alice", "a", "notes.txt", b"hi")
+                .unwrap();
+            let attachment = store.attachment("alice", "a", "shot.png").unwrap().unwrap();
+            assert_eq!(
+                (attachment.mime.as_str(), attachment.bytes.len()),
+                ("image/png", 12)
+            );
+            let names = |store: &dyn Store, file| -> Vec<String> {
+                let rows = store.attachments("alice", file).unwrap();
+                rows.into_iter().map(|(name, _, _)| name).collect()
+            };
+            assert_eq!(names(store.as_ref(), "a"), ["notes.txt", "shot.png"]);
+
+            // they follow the page around
+            store
+                .rename_page("alice", "alice", "a", "alice", "b")
+                .unwrap();
+            assert!(names(store.as_ref(), "a").is_empty());
+            assert_eq!(names(store.as_ref(), "b").len(), 2);
+            store.delete_page("alice", "alice", "b").unwrap();
+            assert!(
+                store
+                    .attachment("alice", "b", "shot.png")
+                    .unwrap()
+                    .is_none()
+            );
+            let deleted_at = store.trash("alice").unwrap()[0].deleted_at;
+            store
+                .restore_page("alice", "alice", "b", deleted_at)
+                .unwrap();
+            assert_eq!(names(store.as_ref(), "b").len(), 2);
+
+            store.detach("alice", "alice", "b", "notes.txt").unwrap();
+            assert!(matches!(
+                store.detach("alice", "alice", "b", "notes.txt"),
+                Err(Ex::PageNotFound)
+            ));
+
+            // and are gone with it
+            store.delete_page("alice", "alice", "b").unwrap();
+            let deleted_at = store.trash("alice").unwrap()[0].deleted_at;
+            store.purge_page("alice", "alice", "b", deleted_at).unwrap();
+            store.create_page("alice", "alice", "b").unwrap();
+            store
+                .restore_page("alice", "alice", "b", deleted_at)
+                .unwrap_err();
+            assert!(names(store.as_ref(), "b").is_empty());
+        }
+    }
+
+    #[test]
+    fn drafts() {
+        for store in stores() {
+            with_users(store.as_ref());
+            store.create_page("alice", "alice", "a").unwrap();
+            store.update_page("alice", "alice", "a", "A", "v1").unwrap();
+            let date = store.page("alice", "a").unwrap().unwrap().date;
+            assert!(matches!(
+                store.save_draft("eve", "alice", "a", "A", "v2"),
+                Err(Ex::PermissionDenied)
+            ));
+            store.save_draft("alice", "alice", "a", "A", "v2").unwrap();
+            store.save_draft("bob", "alice", "a", "B", "v3").unwrap();
+
+            // one per editor, the page is untouched
+            let page = store.page("alice", "a").unwrap().unwrap();
+            assert_eq!((page.markdown.as_str(), page.date), ("v1", date));
+            let draft =
+                |store: &dyn Store, file, editor| store.draft("alice", file, editor).unwrap();
+            assert_eq!(draft(store.as_ref(), "a", "alice").unwrap().markdown, "v2");
+            assert_eq!(draft(store.as_ref(), "a", "bob").unwrap().title, "B");
+
+            // publishing drops the publisher's draft only
+            store.update_page("alice", "alice", "a", "A", "v2").unwrap();
+            assert!(draft(store.as_ref(), "a", "alice").is_none());
+            store
+                .rename_page("alice", "alice", "a", "alice", "b")
+                .unwrap();
+            assert!(draft(store.as_ref(), "b", "bob").is_some());
+            store.discard_draft("bob", "alice", "b").unwrap();
+            assert!(draft(store.as_ref(), "b", "bob").is_none());
+
+            store.save_draft("bob", "alice", "b", "B", "v3").unwrap();
+            store.delete_page("alice", "alice", "b").unwrap();
+            assert!(draft(store.as_ref(), "b", "bob").is_none());
+        }
+    }
+
+    #[test]
+    fn listing() {
+        for store in stores() {
+            with_users(store.as_ref());
+            for (user, file, title) in [
+                ("bob", "x", "banana"),
+                ("alice", "y", "Cherry"),
+                ("alice", "z", "apple"),
+            ] {
+                store.create_page(user, user, file).unwrap();
+                store.update_page(user, user, file, title, "").unwrap();
+            }
+            let files = |sort, after: Option<&Cursor>, limit| {
+                let rows = store.list_pages(sort, after, limit).unwrap();
+                let cursor = rows.last().map(|(u, f, p)| Cursor::new(u, f, p));
+                let files: Vec<String> = rows.into_iter().map(|(_, file, _)| file).collect();
+                (files, cursor)
+            };
+
+            let (first, cursor) = files(Sort::Author, None, 2);
+            assert_eq!(first, ["y", "z"]);
+            assert_eq!(files(Sort::Author, cursor.as_ref(), 2).0, ["x"]);
+
+            let (first, cursor) = files(Sort::Title, None, 1);
+            assert_eq!(first, ["z"]);
+            assert_eq!(files(Sort::Title, cursor.as_ref(), 5).0, ["x", "y"]);
+
+            // same second, newest (date, user, file) first
+            let (all, _) = files(Sort::Date, None, 5);
+            assert_eq!(all.len(), 3);
+            store.delete_page("bob", "bob", "x").unwrap();
+            let (first, cursor) = files(Sort::Date, None, 1);
+            assert_eq!(files(Sort::Date, cursor.as_ref(), 5).0.len(), 1);
+            assert!(!first.contains(&"x".to_string()));
+
+            // one user's, newest (date, file) first
+            let files = |limit| -> Vec<String> {
+                let rows = store.user_pages("alice", limit).unwrap();
+                rows.into_iter().map(|(file, _)| file).collect()
+            };
+            assert_eq!(files(5), ["z", "y"]);
+            assert_eq!(files(1), ["z"]);
+            assert!(store.user_pages("nobody", 5).unwrap().is_empty());
+        }
+    }
+}
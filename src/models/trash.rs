@@ -0,0 +1,91 @@
+use crate::config::Config;
+use crate::models::attachments;
+use crate::models::pages::{Page, PageData};
+use crate::models::types::Result;
+use redb::{Database, ReadableTable, TableDefinition};
+use std::sync::Arc;
+
+/// (user, file, deleted_at): (deleted_by, PageData), deleted pages
+pub const TRASH: TableDefinition<(&str, &str, i64), (&str, PageData)> =
+    TableDefinition::new("trash");
+
+/// a deleted page, as handed out by a `Store`
+#[derive(Debug, Clone)]
+pub struct Trashed {
+    pub file: String,
+    pub deleted_at: i64,
+    pub deleted_by: String,
+    pub page: Page,
+}
+
+/// drop pages deleted before `before` and their attachments, return how many pages went
+pub fn purge(db: &Database, before: i64) -> Result<usize> {
+    let write_txn = db.begin_write()?;
+    let count = {
+        let mut trash = write_txn.open_table(TRASH)?;
+        let mut expired = vec![];
+        for entry in trash.iter()? {
+            let (key, _) = entry?;
+            let (user, file, deleted_at) = key.value();
+            if deleted_at < before {
+                expired.push((user.to_string(), file.to_string(), deleted_at));
+            }
+        }
+        for (user, file, deleted_at) in &expired {
+            trash.remove((user.as_str(), file.as_str(), *deleted_at))?;
+            attachments::purge(&write_txn, user, file, *deleted_at)?;
+        }
+        expired.len()
+    };
+    write_txn.commit()?;
+    Ok(count)
+}
+
+/// purge pages older than `trash_days` once an hour, runs forever
+pub async fn schedule(db: Arc<Database>, config: Arc<Config>) {
+    let retention = config.trash_days as i64 * 86400;
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+
+    loop {
+        interval.tick().await;
+        let db = db.clone();
+        let before = time::UtcDateTime::now().unix_timestamp() - retention;
+        match tokio::task::spawn_blocking(move || purge(&db, before)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(count)) => println!("Purged {count} pages from the trash"),
+            Ok(Err(e)) => eprintln!("Scheduled trash purge failed: {e:?}"),
+            Err(e) => eprintln!("Scheduled trash purge panicked: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::redb_store::RedbStore;
+    use crate::models::store::Store;
+    use crate::models::users::UserData;
+    use redb::backends::InMemoryBackend;
+
+    #[test]
+    fn purges_expired_pages() {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .unwrap();
+        let db = Arc::new(db);
+        let store = RedbStore::new(db.clone());
+        store
+            .create_user("alice", UserData::default(), None)
+            .unwrap();
+        for file in ["a", "b"] {
+            store.create_page("alice", "alice", file).unwrap();
+            store.delete_page("alice", "alice", file).unwrap();
+        }
+
+        let trash = store.trash("alice").unwrap();
+        let (newest, oldest) = (trash[0].deleted_at, trash[1].deleted_at);
+        assert_eq!(purge(&db, oldest).unwrap(), 0);
+        assert_eq!(purge(&db, newest + 1).unwrap(), 2);
+        assert!(store.trash("alice").unwrap().is_empty());
+    }
+}
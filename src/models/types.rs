@@ -2,20 +2,25 @@ use askama::Template;
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse, Response};
-use redb::Database;
 use std::sync::Arc;
 
-use crate::config::CONFIG;
+use crate::app::Site;
+use crate::config::Config;
 
-pub type AppState = State<Arc<Database>>;
+pub type AppState = State<Arc<Site>>;
 
 pub type Result<T> = std::result::Result<T, Ex>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Ex {
     InvalidUsername,
     InvalidFilename,
+    InvalidTitle,
     InvalidTimestamp,
+    InvalidCursor,
+    InvalidImage,
+    InvalidEdit,
+    ContentTooLarge,
     FileExists,
     UserExists,
     UserNotFound,
@@ -31,7 +36,11 @@ pub enum Ex {
     DatabaseStorageError,
     DatabaseTransactionError,
     DataEncodingError,
+    UnsupportedSchemaVersion,
+    InvalidSnapshot,
+    FileSystemError,
     TemplateRenderingError,
+    NotSupported,
     InternalServerError,
 }
 
@@ -47,6 +56,12 @@ impl From<redb::Error> for Ex {
     }
 }
 
+impl From<redb::DatabaseError> for Ex {
+    fn from(_: redb::DatabaseError) -> Self {
+        Ex::DatabaseError
+    }
+}
+
 impl From<redb::TableError> for Ex {
     fn from(_: redb::TableError) -> Self {
         Ex::DatabaseTableError
@@ -71,24 +86,22 @@ impl From<redb::TransactionError> for Ex {
     }
 }
 
+impl From<std::io::Error> for Ex {
+    fn from(_: std::io::Error) -> Self {
+        Ex::FileSystemError
+    }
+}
+
 impl From<askama::Error> for Ex {
     fn from(_: askama::Error) -> Self {
         Ex::TemplateRenderingError
     }
 }
 
-impl IntoResponse for Ex {
-    fn into_response(self) -> Response {
-        #[derive(Template)]
-        #[template(path = "error.html")]
-        struct Page<'a> {
-            base_url: &'a str,
-            site_title: &'a str,
-            title: &'a str,
-            message: &'a str,
-        }
-
-        let (status_code, title, message) = match self {
+impl Ex {
+    /// (status, title, message) shown on the error page
+    pub fn describe(self) -> (StatusCode, &'static str, &'static str) {
+        match self {
             Ex::InvalidUsername => (
                 StatusCode::BAD_REQUEST,
                 "Invalid Username",
@@ -99,11 +112,36 @@ impl IntoResponse for Ex {
                 "Invalid Filename",
                 "The filename you provided contains invalid characters or is too long. Please use a different filename that meets the system requirements.",
             ),
+            Ex::InvalidTitle => (
+                StatusCode::BAD_REQUEST,
+                "Invalid Title",
+                "The page title is empty or too long. Please give the page a title of at most 256 characters.",
+            ),
             Ex::InvalidTimestamp => (
                 StatusCode::BAD_REQUEST,
                 "Invalid Timestamp",
                 "The timestamp format is incorrect. Please ensure it follows the expected format and represents a valid date/time.",
             ),
+            Ex::InvalidCursor => (
+                StatusCode::BAD_REQUEST,
+                "Invalid Cursor",
+                "The listing position in this link could not be read. It may have been cut off or edited. Please start again from the first page.",
+            ),
+            Ex::InvalidImage => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Invalid Image",
+                "The uploaded file could not be read as an image. Please use a PNG, JPEG, GIF or WebP image of a reasonable size.",
+            ),
+            Ex::InvalidEdit => (
+                StatusCode::CONFLICT,
+                "Invalid Edit",
+                "The edit does not fit the current version of the page. Please reload the editor to pick up the latest changes.",
+            ),
+            Ex::ContentTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Content Too Large",
+                "The content you sent is larger than the server accepts for this operation. Please shorten it and try again.",
+            ),
             Ex::FileExists => (
                 StatusCode::CONFLICT,
                 "File Exists",
@@ -179,24 +217,66 @@ impl IntoResponse for Ex {
                 "Data Encoding Error",
                 "There was an error encoding or decoding data. This is a system issue that our technical team will investigate.",
             ),
+            Ex::UnsupportedSchemaVersion => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Unsupported Schema Version",
+                "The database was written by a newer version of this software and cannot be opened safely. Please upgrade the server before continuing.",
+            ),
+            Ex::InvalidSnapshot => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Invalid Snapshot",
+                "The database snapshot failed verification. It may be truncated, corrupted or written by an incompatible version, and cannot be restored.",
+            ),
+            Ex::FileSystemError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "File System Error",
+                "A file could not be read or written on the server. This may be due to missing directories, permissions or disk space. Our team has been alerted.",
+            ),
             Ex::TemplateRenderingError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Template Error",
                 "The system encountered an error while rendering the page template. This is likely a temporary issue. Please refresh the page or try again later.",
             ),
+            Ex::NotSupported => (
+                StatusCode::NOT_IMPLEMENTED,
+                "Not Supported",
+                "This operation is not available with the storage backend the server is running on.",
+            ),
             Ex::InternalServerError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Server Error",
                 "An unexpected internal server error occurred. Our technical team has been notified and is working to resolve the issue. We apologize for the inconvenience.",
             ),
-        };
+        }
+    }
+
+    /// the error page, rendered for `config`'s site
+    pub fn render(self, config: &Config) -> Response {
+        #[derive(Template)]
+        #[template(path = "error.html")]
+        struct Page<'a> {
+            base_url: &'a str,
+            site_title: &'a str,
+            title: &'a str,
+            message: &'a str,
+        }
 
+        let (status_code, title, message) = self.describe();
         let page = Page {
-            base_url: CONFIG.base_url,
-            site_title: CONFIG.site_title,
-            title: title,
+            base_url: &config.base_url,
+            site_title: &config.site_title,
+            title,
             message,
         };
         (status_code, Html(page.render().unwrap())).into_response()
     }
 }
+
+// the page itself is rendered by the app's error middleware, which knows the site
+impl IntoResponse for Ex {
+    fn into_response(self) -> Response {
+        let mut response = self.describe().0.into_response();
+        response.extensions_mut().insert(self);
+        response
+    }
+}
@@ -1,13 +1,16 @@
-use crate::config::CONFIG;
+use crate::config::Config;
+use crate::models::schema;
+use crate::models::store::Store;
 use crate::models::types::{Ex, Result};
 use crate::token::Token;
-use redb::{Database, ReadableTable, TableDefinition};
+use redb::TableDefinition;
 use std::collections::BTreeSet;
 
 /// user: UserData
 pub const USERS: TableDefinition<&str, UserData> = TableDefinition::new("users");
 
 #[derive(Debug, Clone)]
+#[cfg_attr(test, derive(Default))]
 pub struct UserData {
     passwd: [u8; 32],
     pub collabs: BTreeSet<String>,
@@ -16,7 +19,13 @@ pub struct UserData {
 
 impl UserData {
     /// sign up a user
-    pub fn sign_up(db: &Database, user: &str, passwd: &str, invite_code: &str) -> Result<()> {
+    pub fn sign_up(
+        config: &Config,
+        store: &dyn Store,
+        user: &str,
+        passwd: &str,
+        invite_code: &str,
+    ) -> Result<()> {
         #[inline]
         fn validate_name(n: &str) -> bool {
             n.chars()
@@ -25,68 +34,43 @@ impl UserData {
         }
 
         // check
-        let Some(inviter) = Token::parse(invite_code, CONFIG.secret_invite) else {
+        let Some(inviter) = Token::parse(invite_code, &config.secret_invite) else {
             return Err(Ex::InvalidInvite);
         };
         if !validate_name(user) {
             return Err(Ex::InvalidUsername);
         }
 
-        let mut user_data = Self::new(passwd);
-        let write_txn = db.begin_write()?;
-        let mut users_table = write_txn.open_table(USERS)?;
-
-        // check user exists
-        if users_table.get(user)?.is_some() {
-            return Err(Ex::UserExists);
-        }
-
         // connect node (except for root)
-        if !inviter.is_empty() {
-            let mut inviter_entry = users_table
-                .get_mut(inviter.as_str())?
-                .ok_or(Ex::InvalidInvite)?;
-            let mut inviter_data = inviter_entry.value().clone();
-            inviter_data.collabs.insert(user.to_string());
-            inviter_entry.insert(inviter_data)?;
-            user_data.collabs.insert(inviter);
-        }
-
-        users_table.insert(user, user_data)?;
-        drop(users_table);
-        write_txn.commit()?;
+        let inviter = (!inviter.is_empty()).then_some(inviter.as_str());
+        store.create_user(user, Self::new(config, passwd), inviter)?;
         println!("Signed up user: {}", user);
         Ok(())
     }
 
     /// parse an invite code, return inviter's profile url
-    pub fn link_collab(db: &Database, user: &str, invite_code: &str) -> Result<String> {
+    pub fn link_collab(
+        config: &Config,
+        store: &dyn Store,
+        user: &str,
+        invite_code: &str,
+    ) -> Result<String> {
         // check
-        let Some(inviter) = Token::parse(invite_code, CONFIG.secret_invite) else {
+        let Some(inviter) = Token::parse(invite_code, &config.secret_invite) else {
             return Err(Ex::InvalidInvite);
         };
         if inviter.is_empty() || inviter == user {
-            return Ok(format!("{}@{}", CONFIG.base_url, user));
+            return Ok(Self::get_profile_url(config, user));
         }
 
-        let write_txn = db.begin_write()?;
-        {
-            // connect node
-            let mut users_table = write_txn.open_table(USERS)?;
-            let mut inviter_entry = users_table
-                .get_mut(inviter.as_str())?
-                .ok_or(Ex::InvalidInvite)?;
-            let mut inviter_data = inviter_entry.value().clone();
-            inviter_data.collabs.insert(user.to_string());
-            inviter_entry.insert(&inviter_data)?;
-        }
-        write_txn.commit()?;
-        Ok(Self::get_profile_url(&inviter))
+        // connect node
+        store.link_collab(&inviter, user)?;
+        Ok(Self::get_profile_url(config, &inviter))
     }
 
-    pub fn new(passwd: &str) -> Self {
+    pub fn new(config: &Config, passwd: &str) -> Self {
         Self {
-            passwd: Self::hash_passwd(passwd),
+            passwd: Self::hash_passwd(config, passwd),
             collabs: BTreeSet::new(),
             files: BTreeSet::new(),
         }
@@ -94,29 +78,39 @@ impl UserData {
 
     // password
 
-    pub fn verify_passwd(&self, passwd: &str) -> Result<()> {
-        match self.passwd == Self::hash_passwd(passwd) {
+    pub fn verify_passwd(&self, config: &Config, passwd: &str) -> Result<()> {
+        match self.passwd == Self::hash_passwd(config, passwd) {
             true => Ok(()),
             false => Err(Ex::InvalidCredentials),
         }
     }
-    pub fn update_passwd(&mut self, old_passwd: &str, passwd: &str) -> Result<()> {
-        self.verify_passwd(old_passwd)?;
-        self.passwd = Self::hash_passwd(passwd);
+    pub fn update_passwd(&mut self, config: &Config, old_passwd: &str, passwd: &str) -> Result<()> {
+        self.verify_passwd(config, old_passwd)?;
+        self.passwd = Self::hash_passwd(config, passwd);
         Ok(())
     }
-    fn hash_passwd(passwd: &str) -> [u8; 32] {
+    fn hash_passwd(config: &Config, passwd: &str) -> [u8; 32] {
         use sha3::{Digest, Sha3_256};
         let mut hasher = Sha3_256::new();
-        hasher.update(CONFIG.secret_passwd);
+        hasher.update(&config.secret_passwd);
         hasher.update(passwd);
         hasher.finalize().into()
     }
 
+    // permissions
+
+    /// `auth` may edit the pages of `owner` (whose data this is)
+    pub fn check_edit(&self, auth: &str, owner: &str) -> Result<()> {
+        match auth == owner || self.collabs.contains(auth) {
+            true => Ok(()),
+            false => Err(Ex::PermissionDenied),
+        }
+    }
+
     // util
 
-    pub fn get_profile_url(user: &str) -> String {
-        format!("{}@{user}", CONFIG.base_url)
+    pub fn get_profile_url(config: &Config, user: &str) -> String {
+        format!("{}@{user}", config.base_url)
     }
 }
 
@@ -132,8 +126,12 @@ impl redb::Value for UserData {
     where
         Self: 'a,
     {
-        let (passwd, collabs, files) =
-            <([u8; 32], Vec<String>, Vec<String>) as redb::Value>::from_bytes(data);
+        let (passwd, collabs, files) = match schema::decode(data) {
+            (1, payload) => {
+                <([u8; 32], Vec<String>, Vec<String>) as redb::Value>::from_bytes(payload)
+            }
+            (version, _) => panic!("unsupported UserData encoding v{version}"),
+        };
         UserData {
             passwd,
             collabs: BTreeSet::from_iter(collabs),
@@ -145,11 +143,12 @@ impl redb::Value for UserData {
     where
         Self: 'b,
     {
-        <([u8; 32], Vec<String>, Vec<String>) as redb::Value>::as_bytes(&(
+        let payload = <([u8; 32], Vec<String>, Vec<String>) as redb::Value>::as_bytes(&(
             value.passwd,
             Vec::from_iter(value.collabs.clone()),
             Vec::from_iter(value.files.clone()),
-        ))
+        ));
+        schema::encode(1, &payload)
     }
 
     fn type_name() -> redb::TypeName {
@@ -0,0 +1,1483 @@
+//! end-to-end tests, driving the router in-process against a temporary database
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode, header};
+use redb::Database;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tower::ServiceExt;
+
+use note::config::Config;
+use note::token::Token;
+
+struct TestApp {
+    router: Router,
+    db_paths: Vec<PathBuf>,
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        for path in &self.db_paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+struct Resp {
+    status: StatusCode,
+    cookie: Option<String>,
+    location: Option<String>,
+    body: String,
+}
+
+const SECRET_INVITE: &str = "test-invite-secret";
+
+/// a site served from `base_url`, with its cookies scoped to `cookie_path`
+fn test_config(base_url: &str, cookie_path: &str) -> Config {
+    let site_root = std::env::temp_dir().join(format!("note-test-{}", std::process::id()));
+    std::fs::create_dir_all(&site_root).unwrap();
+    Config {
+        server_addr: "127.0.0.1:0".to_string(),
+        database_path: "unused.redb".to_string(),
+        site_root: site_root.display().to_string(),
+        base_url: base_url.to_string(),
+        cookie_path: cookie_path.to_string(),
+        site_title: "Test Notes".to_string(),
+        secret_invite: SECRET_INVITE.to_string(),
+        secret_passwd: "test-passwd-secret".to_string(),
+        admins: vec!["root".to_string()],
+        backup_dir: "unused-backups".to_string(),
+        backup_interval: 0,
+        backup_retention: 7,
+        cache_control: "public, max-age=60".to_string(),
+        page_cache_entries: 64,
+        page_cache_bytes: 1 << 20,
+        feed_limit: 2,
+        max_attachment_bytes: 1024,
+        trash_days: 30,
+        host: None,
+        prefix: None,
+    }
+}
+
+/// a fresh database file, removed with the `TestApp`
+fn test_db() -> (Arc<Database>, PathBuf) {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let db_path = std::env::temp_dir().join(format!("note-test-{}-{n}.redb", std::process::id()));
+    let db = Database::create(&db_path).unwrap();
+    (Arc::new(db), db_path)
+}
+
+fn app() -> TestApp {
+    let (db, db_path) = test_db();
+    let router = note::app(test_config("http://note.test/", "/"), db).unwrap();
+    TestApp {
+        router,
+        db_paths: vec![db_path],
+    }
+}
+
+impl TestApp {
+    async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        cookie: Option<&str>,
+        json: Option<&str>,
+    ) -> Resp {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let body = match json {
+            Some(json) => {
+                request = request.header(header::CONTENT_TYPE, "application/json");
+                Body::from(json.to_string())
+            }
+            None => Body::empty(),
+        };
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request.body(body).unwrap())
+            .await
+            .unwrap();
+        let header = |name| {
+            let value = response.headers().get(name)?;
+            Some(value.to_str().unwrap().to_string())
+        };
+        let cookie = header(header::SET_COOKIE).map(|c| c.split(';').next().unwrap().to_string());
+        let location = header(header::LOCATION);
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        Resp {
+            status,
+            cookie,
+            location,
+            body: String::from_utf8(body.to_vec()).unwrap(),
+        }
+    }
+
+    async fn get(&self, uri: &str, cookie: Option<&str>) -> Resp {
+        self.send(Method::GET, uri, cookie, None).await
+    }
+
+    /// sign up with an invite from `inviter` ("" for root), return the cookie
+    async fn sign_up(&self, user: &str, inviter: &str) -> String {
+        let invite = Token::new(inviter, 900, SECRET_INVITE);
+        let json = format!(r#"["{user}", "passwd-{user}", "{invite}"]"#);
+        let resp = self
+            .send(Method::POST, "/auth/sign-up", None, Some(&json))
+            .await;
+        assert_eq!(resp.status, StatusCode::OK, "sign up {user}: {}", resp.body);
+        resp.cookie.unwrap()
+    }
+}
+
+#[tokio::test]
+async fn sign_up_via_invite() {
+    let app = app();
+    let json = r#"["alice", "passwd", "not-an-invite-code-at-all-not-an-invite-code"]"#;
+    let resp = app
+        .send(Method::POST, "/auth/sign-up", None, Some(json))
+        .await;
+    assert_eq!(resp.status, StatusCode::UNAUTHORIZED);
+
+    let cookie = app.sign_up("alice", "").await;
+    assert!(cookie.starts_with("token="));
+
+    // the cookie signs alice in
+    let resp = app.get("/", Some(&cookie)).await;
+    assert!(resp.body.contains("Sign Out"));
+
+    // name taken
+    let invite = Token::new("", 900, SECRET_INVITE);
+    let json = format!(r#"["alice", "passwd", "{invite}"]"#);
+    let resp = app
+        .send(Method::POST, "/auth/sign-up", None, Some(&json))
+        .await;
+    assert_eq!(resp.status, StatusCode::CONFLICT);
+
+    // invalid name
+    let json = format!(r#"["a!", "passwd", "{invite}"]"#);
+    let resp = app
+        .send(Method::POST, "/auth/sign-up", None, Some(&json))
+        .await;
+    assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+
+    // expired invite
+    let expired = Token::new("", -1, SECRET_INVITE);
+    let json = format!(r#"["bob", "passwd", "{expired}"]"#);
+    let resp = app
+        .send(Method::POST, "/auth/sign-up", None, Some(&json))
+        .await;
+    assert_eq!(resp.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn sign_in_and_out() {
+    let app = app();
+    app.sign_up("alice", "").await;
+
+    let resp = app
+        .send(
+            Method::POST,
+            "/auth/sign-in",
+            None,
+            Some(r#"["alice", "wrong"]"#),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::UNAUTHORIZED);
+    let resp = app
+        .send(
+            Method::POST,
+            "/auth/sign-in",
+            None,
+            Some(r#"["nobody", "x"]"#),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::UNAUTHORIZED);
+
+    let resp = app
+        .send(
+            Method::POST,
+            "/auth/sign-in",
+            None,
+            Some(r#"["alice", "passwd-alice"]"#),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let cookie = resp.cookie.unwrap();
+
+    // signed in users are sent away from the auth page
+    let resp = app.get("/auth", Some(&cookie)).await;
+    assert_eq!(resp.status, StatusCode::SEE_OTHER);
+    let resp = app.get("/auth", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+
+    // signing out clears the cookie
+    let resp = app.get("/auth/sign-out", Some(&cookie)).await;
+    assert_eq!(resp.cookie.as_deref(), Some("token="));
+}
+
+#[tokio::test]
+async fn page_create_update_delete() {
+    let app = app();
+    let cookie = app.sign_up("alice", "").await;
+
+    let resp = app
+        .send(Method::PUT, "/page/alice/notes", Some(&cookie), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app
+        .send(Method::PUT, "/page/alice/notes", Some(&cookie), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::CONFLICT);
+    let resp = app
+        .send(Method::PUT, "/page/alice/bad%20name", Some(&cookie), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+
+    let resp = app.get("/@alice/notes", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("Untitled"));
+
+    let json = r#"["My Notes", "some **bold** text"]"#;
+    let resp = app
+        .send(Method::POST, "/page/alice/notes", Some(&cookie), Some(json))
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app.get("/@alice/notes", None).await;
+    assert!(resp.body.contains("My Notes"));
+    assert!(resp.body.contains("<strong>bold</strong>"));
+
+    let resp = app.get("/@alice/notes/edit", Some(&cookie)).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("some **bold** text"));
+
+    let resp = app.get("/@alice", None).await;
+    assert!(resp.body.contains("My Notes"));
+    let resp = app.get("/", None).await;
+    assert!(resp.body.contains("My Notes"));
+
+    let resp = app
+        .send(Method::DELETE, "/page/alice/notes", Some(&cookie), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app.get("/@alice/notes", None).await;
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+    let resp = app.get("/@alice", None).await;
+    assert!(!resp.body.contains("My Notes"));
+}
+
+#[tokio::test]
+async fn permission_denials() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let eve = app.sign_up("eve", "").await;
+    app.send(Method::PUT, "/page/alice/notes", Some(&alice), None)
+        .await;
+
+    // anonymous
+    let resp = app.send(Method::PUT, "/page/alice/other", None, None).await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app
+        .send(
+            Method::POST,
+            "/page/alice/notes",
+            None,
+            Some(r#"["x", "x"]"#),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app
+        .send(Method::DELETE, "/page/alice/notes", None, None)
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app.get("/@alice/notes/edit", None).await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    assert!(resp.body.contains("Sign In"));
+
+    // signed in, but not a collaborator
+    let resp = app
+        .send(Method::PUT, "/page/alice/other", Some(&eve), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let json = r#"["x", "x"]"#;
+    let resp = app
+        .send(Method::POST, "/page/alice/notes", Some(&eve), Some(json))
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app
+        .send(Method::DELETE, "/page/alice/notes", Some(&eve), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app.get("/@alice/notes/edit", Some(&eve)).await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    // forged cookie
+    let resp = app
+        .send(Method::PUT, "/page/alice/other", Some("token=alice"), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    // admin endpoints
+    let resp = app
+        .send(Method::POST, "/admin/rerender", Some(&alice), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    let resp = app.get("/@alice/notes", None).await;
+    assert!(resp.body.contains("Untitled"));
+}
+
+#[tokio::test]
+async fn invite_linking() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+
+    // signing up with alice's invite links both ways
+    let bob = app.sign_up("bob", "alice").await;
+    let resp = app
+        .send(Method::PUT, "/page/alice/from-bob", Some(&bob), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app
+        .send(Method::PUT, "/page/bob/from-alice", Some(&alice), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+
+    // visiting alice's invite lets carol edit alice's pages, not the other way round
+    let carol = app.sign_up("carol", "").await;
+    let invite = Token::new("alice", 900, SECRET_INVITE);
+    let resp = app.get(&format!("/invite/{invite}"), Some(&carol)).await;
+    assert_eq!(resp.status, StatusCode::SEE_OTHER);
+    assert_eq!(resp.location.as_deref(), Some("http://note.test/@alice"));
+    let resp = app
+        .send(Method::PUT, "/page/alice/from-carol", Some(&carol), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app
+        .send(Method::PUT, "/page/carol/from-alice", Some(&alice), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+
+    let resp = app.get("/@alice", None).await;
+    assert!(resp.body.contains("@bob"));
+    assert!(resp.body.contains("@carol"));
+
+    // anonymous visitors are asked to sign in first
+    let resp = app.get(&format!("/invite/{invite}"), None).await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    assert!(resp.body.contains("Sign Up"));
+
+    // bad invites
+    let resp = app.get("/invite/garbage", Some(&carol)).await;
+    assert_eq!(resp.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn probes() {
+    let app = app();
+    let resp = app.get("/healthz", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app.get("/readyz", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains(r#""status":"ready""#));
+}
+
+#[tokio::test]
+async fn mounted_under_sub_path() {
+    let (db, db_path) = test_db();
+    let config = test_config("http://host.test/notes/", "/notes/");
+    let router = Router::new().nest("/notes", note::app(config, db).unwrap());
+    let app = TestApp {
+        router,
+        db_paths: vec![db_path],
+    };
+
+    let resp = app.get("/notes/healthz", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+
+    // cookies and links stay under the mount path
+    let invite = Token::new("", 900, SECRET_INVITE);
+    let json = format!(r#"["alice", "passwd-alice", "{invite}"]"#);
+    let request = Request::post("/notes/auth/sign-up")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json))
+        .unwrap();
+    let response = app.router.clone().oneshot(request).await.unwrap();
+    let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.contains("Path=/notes/"), "{set_cookie}");
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+    let resp = app.get("/notes/@alice", Some(&cookie)).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("http://host.test/notes/"));
+
+    // errors render with this site's settings
+    let resp = app.get("/notes/@nobody", None).await;
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+    assert!(resp.body.contains("Test Notes"));
+    assert!(resp.body.contains("http://host.test/notes/"));
+}
+
+#[tokio::test]
+async fn multi_site_isolation() {
+    let (eng_db, eng_path) = test_db();
+    let (ops_db, ops_path) = test_db();
+    let mut eng = test_config("http://eng.test/", "/");
+    eng.host = Some("eng.test".to_string());
+    eng.site_title = "Eng Wiki".to_string();
+    let mut ops = test_config("http://wiki.test/ops/", "/ops/");
+    ops.prefix = Some("/ops".to_string());
+    ops.site_title = "Ops Wiki".to_string();
+    ops.secret_invite = "ops-invite-secret".to_string();
+    let router = note::sites(vec![(eng, eng_db), (ops, ops_db)]).unwrap();
+    let app = TestApp {
+        router,
+        db_paths: vec![eng_path, ops_path],
+    };
+
+    // routed by host (port ignored) and by prefix
+    let resp = app.get("http://eng.test:8080/@nobody", None).await;
+    assert!(resp.body.contains("Eng Wiki"));
+    let resp = app.get("http://wiki.test/ops/@nobody", None).await;
+    assert!(resp.body.contains("Ops Wiki"));
+    let resp = app.get("http://other.test/healthz", None).await;
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+
+    // crawlers find a prefixed site from the root of its host
+    let resp = app.get("http://wiki.test/robots.txt", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("Disallow: /ops/auth\n"));
+    assert!(
+        resp.body
+            .contains("Sitemap: http://wiki.test/ops/sitemap.xml\n")
+    );
+    let resp = app.get("http://eng.test/robots.txt", None).await;
+    assert!(!resp.body.contains("/ops/"));
+
+    // users and pages live in one site only
+    let invite = Token::new("", 900, SECRET_INVITE);
+    let json = format!(r#"["alice", "passwd-alice", "{invite}"]"#);
+    let resp = app
+        .send(
+            Method::POST,
+            "http://eng.test/auth/sign-up",
+            None,
+            Some(&json),
+        )
+        .await;
+    let cookie = resp.cookie.unwrap();
+    let resp = app
+        .send(
+            Method::PUT,
+            "http://eng.test/page/alice/a",
+            Some(&cookie),
+            None,
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app.get("http://eng.test/@alice/a", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app.get("http://wiki.test/ops/@alice", None).await;
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+
+    // neither sessions nor invites carry over
+    let resp = app
+        .send(
+            Method::PUT,
+            "http://wiki.test/ops/page/alice/b",
+            Some(&cookie),
+            None,
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app
+        .send(
+            Method::POST,
+            "http://wiki.test/ops/auth/sign-up",
+            None,
+            Some(&json),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn trailing_slash_normalization() {
+    let app = app();
+    let cookie = app.sign_up("alice", "").await;
+
+    // pages redirect to the canonical url, keeping the query
+    let resp = app.get("/auth/?x=1", None).await;
+    assert_eq!(resp.status, StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(resp.location.as_deref(), Some("http://note.test/auth?x=1"));
+    let resp = app.get("/@alice//", None).await;
+    assert_eq!(resp.location.as_deref(), Some("http://note.test/@alice"));
+    let resp = app.get("/", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+
+    // api calls are served either way
+    let resp = app
+        .send(Method::PUT, "/page/alice/a/", Some(&cookie), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let json = r#"["A", "text"]"#;
+    let resp = app
+        .send(Method::POST, "/page/alice/a", Some(&cookie), Some(json))
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+
+    let resp = app.get("/@alice/a", None).await;
+    assert!(
+        resp.body
+            .contains(r#"<link rel="canonical" href="http://note.test/@alice/a" />"#)
+    );
+    // static directories keep their slash, and serve their index.html
+    let site_root = std::env::temp_dir().join(format!("note-test-{}", std::process::id()));
+    std::fs::create_dir_all(site_root.join("guide")).unwrap();
+    std::fs::write(site_root.join("guide/index.html"), "<h1>Guide</h1>").unwrap();
+    let resp = app.get("/guide?x=1", None).await;
+    assert_eq!(resp.status, StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        resp.location.as_deref(),
+        Some("http://note.test/guide/?x=1")
+    );
+    let resp = app.get("/guide//", None).await;
+    assert_eq!(resp.location.as_deref(), Some("http://note.test/guide/"));
+    let resp = app.get("/guide/", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("<h1>Guide</h1>"));
+    // routes come first, the disk isn't looked at for them
+    std::fs::create_dir_all(site_root.join("tags")).unwrap();
+    assert_eq!(app.get("/tags", None).await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn conditional_get() {
+    let app = app();
+    let cookie = app.sign_up("alice", "").await;
+    app.send(Method::PUT, "/page/alice/a", Some(&cookie), None)
+        .await;
+
+    let page = |headers: Vec<(header::HeaderName, String)>| {
+        let mut request = Request::get("/@alice/a");
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        app.router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let resp = page(vec![]).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let header = |name| resp.headers()[name].to_str().unwrap().to_string();
+    let (etag, modified) = (header(header::ETAG), header(header::LAST_MODIFIED));
+    assert_eq!(header(header::CACHE_CONTROL), "public, max-age=60");
+
+    // same version
+    let resp = page(vec![(header::IF_NONE_MATCH, etag.clone())])
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()[header::ETAG], etag.as_str());
+    let resp = page(vec![(header::IF_MODIFIED_SINCE, modified.clone())])
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    // If-None-Match decides when both are sent
+    let stale = vec![
+        (header::IF_NONE_MATCH, r#"W/"other""#.to_string()),
+        (header::IF_MODIFIED_SINCE, modified),
+    ];
+    assert_eq!(page(stale).await.unwrap().status(), StatusCode::OK);
+
+    // an edit changes the etag
+    let json = r#"["A", "text"]"#;
+    app.send(Method::POST, "/page/alice/a", Some(&cookie), Some(json))
+        .await;
+    let resp = page(vec![(header::IF_NONE_MATCH, etag)]).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    // signed-in readers aren't cached publicly
+    let resp = page(vec![(header::COOKIE, cookie)]).await.unwrap();
+    assert_eq!(resp.headers()[header::CACHE_CONTROL], "private, no-cache");
+}
+
+#[tokio::test]
+async fn page_cache() {
+    let app = app();
+    let root = app.sign_up("root", "").await;
+    let alice = app.sign_up("alice", "").await;
+    app.send(Method::PUT, "/page/alice/a", Some(&alice), None)
+        .await;
+    app.send(Method::PUT, "/page/alice/b", Some(&alice), None)
+        .await;
+
+    let stats = || async { app.get("/admin/cache", Some(&root)).await.body };
+    assert!(app.get("/@alice/a", None).await.body.contains("Untitled"));
+    app.get("/@alice/a", None).await;
+    app.get("/", None).await;
+    assert!(stats().await.contains(r#""entries":2,"#));
+    assert!(stats().await.contains(r#""hits":1,"misses":2"#));
+
+    // writes show up right away, for the page and the one linking to it
+    let json = r#"["Bee", "text"]"#;
+    app.send(Method::POST, "/page/alice/b", Some(&alice), Some(json))
+        .await;
+    assert!(app.get("/@alice/a", None).await.body.contains("Bee"));
+    assert!(app.get("/", None).await.body.contains("Bee"));
+    app.send(Method::DELETE, "/page/alice/b", Some(&alice), None)
+        .await;
+    assert!(!app.get("/@alice/a", None).await.body.contains("Bee"));
+    assert_eq!(
+        app.get("/@alice/b", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    let resp = app.get("/admin/cache", Some(&alice)).await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn home_listing() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    for n in 0..55 {
+        let uri = format!("/page/alice/p{n:02}");
+        app.send(Method::PUT, &uri, Some(&alice), None).await;
+        let json = format!(r#"["Title {}", ""]"#, 99 - n);
+        app.send(Method::POST, &uri, Some(&alice), Some(&json))
+            .await;
+    }
+
+    // by title: "Title 45" (p54) first, 50 per screen
+    let resp = app.get("/?sort=title", None).await;
+    let first = resp.body.find("Title 45").unwrap();
+    assert!(first < resp.body.find("Title 46").unwrap());
+    assert!(!resp.body.contains("Title 95"));
+    let start = resp.body.find("?sort=title&after=").unwrap();
+    let next = &resp.body[start..];
+    let next = &next[..next.find('"').unwrap()];
+
+    let resp = app.get(&format!("/{next}"), None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("Title 95"));
+    assert!(resp.body.contains("Title 99"));
+    assert!(!resp.body.contains("Title 94"));
+    assert!(!resp.body.contains("More »"));
+
+    // newest edits first
+    let resp = app.get("/", None).await;
+    assert!(resp.body.contains("More »"));
+    let resp = app.get("/?after=garbage", None).await;
+    assert_eq!(resp.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn feeds() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let bob = app.sign_up("bob", "").await;
+    for (cookie, user, file) in [
+        (&alice, "alice", "a"),
+        (&alice, "alice", "b"),
+        (&bob, "bob", "c"),
+    ] {
+        let uri = format!("/page/{user}/{file}");
+        app.send(Method::PUT, &uri, Some(cookie), None).await;
+        let json = r#"["Fish & <Chips>", "*hi*"]"#;
+        app.send(Method::POST, &uri, Some(cookie), Some(json)).await;
+    }
+
+    // site feed, limited to `feed_limit` entries
+    let resp = app.get("/feed.atom", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.starts_with("<?xml"));
+    assert_eq!(resp.body.matches("<entry>").count(), 2);
+    assert!(resp.body.contains("<id>http://note.test/@bob/c</id>"));
+    assert!(
+        resp.body
+            .contains("<title>Fish &#38; &#60;Chips&#62;</title>")
+    );
+    assert!(resp.body.contains("&#60;em&#62;hi&#60;/em&#62;"));
+
+    // per user
+    let resp = app.get("/@alice/feed.rss", None).await;
+    assert_eq!(resp.body.matches("<item>").count(), 2);
+    assert!(
+        resp.body
+            .contains("<guid isPermaLink=\"true\">http://note.test/@alice/a</guid>")
+    );
+    assert!(!resp.body.contains("@bob/c"));
+    let resp = app.get("/@nobody/feed.atom", None).await;
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn sitemap_and_robots() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    app.send(Method::PUT, "/page/alice/a", Some(&alice), None)
+        .await;
+
+    let resp = app.get("/sitemap.xml", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("<loc>http://note.test/</loc>"));
+    assert!(resp.body.contains("<loc>http://note.test/@alice/a</loc>"));
+    assert!(resp.body.contains("<lastmod>20"));
+
+    let resp = app.get("/robots.txt", None).await;
+    assert!(resp.body.contains("Disallow: /@*/*/edit$\n"));
+    assert!(resp.body.contains("Disallow: /auth\n"));
+    assert!(resp.body.contains("Disallow: /invite/\n"));
+    assert!(
+        resp.body
+            .contains("Sitemap: http://note.test/sitemap.xml\n")
+    );
+
+    // scoped to the mount path
+    let (db, db_path) = test_db();
+    let config = test_config("http://host.test/notes/", "/notes/");
+    let router = Router::new().nest("/notes", note::app(config, db).unwrap());
+    let app = TestApp {
+        router,
+        db_paths: vec![db_path],
+    };
+    let resp = app.get("/notes/robots.txt", None).await;
+    assert!(resp.body.contains("Disallow: /notes/auth\n"));
+}
+
+#[tokio::test]
+async fn page_rename() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let bob = app.sign_up("bob", "alice").await;
+    let eve = app.sign_up("eve", "").await;
+    app.send(Method::PUT, "/page/alice/draft", Some(&alice), None)
+        .await;
+    let json = r#"["Plans", "the plan"]"#;
+    app.send(Method::POST, "/page/alice/draft", Some(&alice), Some(json))
+        .await;
+    // cached under the old name
+    assert_eq!(app.get("/@alice/draft", None).await.status, StatusCode::OK);
+
+    let rename = |cookie, from: &str, to: &str| {
+        let uri = format!("/rename/{from}");
+        let (user, file) = to.split_once('/').unwrap();
+        let json = format!(r#"["{user}", "{file}"]"#);
+        let app = &app;
+        async move {
+            app.send(Method::POST, &uri, Some(cookie), Some(&json))
+                .await
+                .status
+        }
+    };
+    assert_eq!(
+        rename(&eve, "alice/draft", "eve/x").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        rename(&alice, "alice/draft", "alice/bad name").await,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        rename(&alice, "alice/draft", "alice/plans").await,
+        StatusCode::OK
+    );
+    // bob collaborates with alice, so either may move it across
+    assert_eq!(
+        rename(&bob, "alice/plans", "bob/plans").await,
+        StatusCode::OK
+    );
+
+    let resp = app.get("/@bob/plans", None).await;
+    assert!(resp.body.contains("the plan"));
+    for old in ["/@alice/draft", "/@alice/plans"] {
+        let resp = app.get(old, None).await;
+        assert_eq!(resp.status, StatusCode::PERMANENT_REDIRECT, "{old}");
+        assert_eq!(
+            resp.location.as_deref(),
+            Some("http://note.test/@bob/plans")
+        );
+    }
+    assert_eq!(
+        app.get("/@alice/nothing", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    // nothing to redirect to once the page is deleted
+    app.send(Method::DELETE, "/page/bob/plans", Some(&bob), None)
+        .await;
+    for old in ["/@alice/draft", "/@alice/plans"] {
+        let resp = app.get(old, None).await;
+        assert_eq!(resp.status, StatusCode::NOT_FOUND, "{old}");
+    }
+}
+
+#[tokio::test]
+async fn trash_restore_and_purge() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let eve = app.sign_up("eve", "").await;
+    app.send(Method::PUT, "/page/alice/notes", Some(&alice), None)
+        .await;
+    let json = r#"["My Notes", "keep me"]"#;
+    app.send(Method::POST, "/page/alice/notes", Some(&alice), Some(json))
+        .await;
+    app.send(Method::DELETE, "/page/alice/notes", Some(&alice), None)
+        .await;
+    assert_eq!(
+        app.get("/@alice/notes", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    // only editors see the trash
+    assert_eq!(
+        app.get("/trash/alice", None).await.status,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        app.get("/trash/alice", Some(&eve)).await.status,
+        StatusCode::FORBIDDEN
+    );
+    let resp = app.get("/trash/alice", Some(&alice)).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("My Notes"));
+    assert!(resp.body.contains("purged after 30 days"));
+
+    // the restore link carries the deletion time
+    let deleted_at = resp
+        .body
+        .split("trashAction('POST', &#34;notes&#34;, ")
+        .nth(1)
+        .and_then(|rest| rest.split(')').next())
+        .unwrap()
+        .to_string();
+    let uri = format!("/trash/alice/{deleted_at}/notes");
+    let resp = app.send(Method::POST, &uri, Some(&eve), None).await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app.send(Method::POST, &uri, Some(&alice), None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app.get("/@alice/notes", None).await;
+    assert!(resp.body.contains("keep me"));
+
+    // purged pages are gone for good
+    app.send(Method::DELETE, "/page/alice/notes", Some(&alice), None)
+        .await;
+    let deleted_at: i64 = deleted_at.parse().unwrap();
+    let mut purged = false;
+    for at in deleted_at..deleted_at + 5 {
+        let uri = format!("/trash/alice/{at}/notes");
+        purged |= app
+            .send(Method::DELETE, &uri, Some(&alice), None)
+            .await
+            .status
+            == StatusCode::OK;
+    }
+    assert!(purged);
+    let resp = app.get("/trash/alice", Some(&alice)).await;
+    assert!(resp.body.contains("The trash is empty"));
+}
+
+#[tokio::test]
+async fn nested_paths() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    for path in [
+        "projects/alpha/notes",
+        "projects/alpha/todo",
+        "projects/beta",
+        "inbox",
+    ] {
+        let uri = format!("/page/alice/{path}");
+        let resp = app.send(Method::PUT, &uri, Some(&alice), None).await;
+        assert_eq!(resp.status, StatusCode::OK, "{path}");
+    }
+    for bad in ["a//b", "a/edit", "a/b/c/d/e/f/g/h/i", "a/b%20c"] {
+        let uri = format!("/page/alice/{bad}");
+        let resp = app.send(Method::PUT, &uri, Some(&alice), None).await;
+        assert_eq!(resp.status, StatusCode::BAD_REQUEST, "{bad}");
+    }
+    let json = r#"["Alpha Notes", "deep"]"#;
+    let resp = app
+        .send(
+            Method::POST,
+            "/page/alice/projects/alpha/notes",
+            Some(&alice),
+            Some(json),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+
+    // page with breadcrumbs
+    let resp = app.get("/@alice/projects/alpha/notes", None).await;
+    assert!(resp.body.contains("deep"));
+    assert!(
+        resp.body
+            .contains(r#"href="http://note.test/@alice/projects/alpha">alpha</a>"#)
+    );
+    let resp = app
+        .get("/@alice/projects/alpha/notes/edit", Some(&alice))
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("deep"));
+
+    // folder indexes and the top level
+    let resp = app.get("/@alice/projects", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains(">alpha/</a>"));
+    assert!(resp.body.contains("@alice/projects/beta"));
+    assert!(!resp.body.contains("Alpha Notes"));
+    let resp = app.get("/@alice/projects/alpha", None).await;
+    assert!(resp.body.contains("Alpha Notes"));
+    let resp = app.get("/@alice", None).await;
+    assert!(resp.body.contains(">projects/</a>"));
+    assert!(!resp.body.contains("Alpha Notes"));
+    assert_eq!(
+        app.get("/@alice/nothing/here", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        app.get("/@alice/feed.atom", None).await.status,
+        StatusCode::OK
+    );
+
+    // a page above others lists them, and learns about new ones
+    let resp = app
+        .send(Method::PUT, "/page/alice/projects", Some(&alice), None)
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(
+        app.get("/@alice/projects", None)
+            .await
+            .body
+            .contains("@alice/projects/beta")
+    );
+    app.send(
+        Method::PUT,
+        "/page/alice/projects/gamma",
+        Some(&alice),
+        None,
+    )
+    .await;
+    assert!(
+        app.get("/@alice/projects", None)
+            .await
+            .body
+            .contains("@alice/projects/gamma")
+    );
+
+    // moving a folder moves everything under it
+    let resp = app
+        .send(
+            Method::POST,
+            "/rename/alice/projects",
+            Some(&alice),
+            Some(r#"["alice", "archive/2024"]"#),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let resp = app.get("/@alice/archive/2024/alpha/notes", None).await;
+    assert!(resp.body.contains("deep"));
+    let resp = app.get("/@alice/projects/alpha/notes", None).await;
+    assert_eq!(
+        resp.location.as_deref(),
+        Some("http://note.test/@alice/archive/2024/alpha/notes")
+    );
+    let resp = app.get("/@alice", None).await;
+    assert!(resp.body.contains(">archive/</a>"));
+    assert!(!resp.body.contains(">projects/</a>"));
+}
+
+#[tokio::test]
+async fn tags_from_front_matter() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    app.send(Method::PUT, "/page/alice/notes", Some(&alice), None)
+        .await;
+    let json = r#"["Notes", "---\ntags: [Rust, Web Dev]\nsummary: All about it\n---\n# Body"]"#;
+    app.send(Method::POST, "/page/alice/notes", Some(&alice), Some(json))
+        .await;
+
+    let resp = app.get("/@alice/notes", None).await;
+    assert!(!resp.body.contains("tags:"));
+    assert!(
+        resp.body
+            .contains(r#"<meta name="description" content="All about it" />"#)
+    );
+    assert!(
+        resp.body
+            .contains(r#"href="http://note.test/tags/web-dev">#web-dev</a>"#)
+    );
+
+    let resp = app.get("/tags", None).await;
+    assert!(resp.body.contains("#rust <small>1</small>"));
+    let resp = app.get("/tags/Web%20Dev", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.contains("@alice/notes"));
+    assert_eq!(
+        app.get("/tags/nothing", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+
+    // tags go away with the front matter
+    let json = r#"["Notes", "no more tags"]"#;
+    app.send(Method::POST, "/page/alice/notes", Some(&alice), Some(json))
+        .await;
+    assert_eq!(
+        app.get("/tags/rust", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+    assert!(
+        app.get("/tags", None)
+            .await
+            .body
+            .contains("No document has been tagged yet")
+    );
+}
+
+#[tokio::test]
+async fn series_navigation() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    for (file, title, order) in [
+        ("intro", "Intro", 1),
+        ("basics", "Basics", 2),
+        ("zzz", "End", 3),
+    ] {
+        app.send(
+            Method::PUT,
+            &format!("/page/alice/{file}"),
+            Some(&alice),
+            None,
+        )
+        .await;
+        let json = format!(r#"["{title}", "---\nseries: The Book\norder: {order}\n---\ntext"]"#);
+        app.send(
+            Method::POST,
+            &format!("/page/alice/{file}"),
+            Some(&alice),
+            Some(&json),
+        )
+        .await;
+    }
+
+    // previous and next follow the order, not the file names
+    let resp = app.get("/@alice/basics", None).await;
+    assert!(resp.body.contains(
+        r#"href="http://note.test/@alice/intro"
+              >« Intro</a"#
+    ));
+    assert!(resp.body.contains(
+        r#"href="http://note.test/@alice/zzz"
+              >End »</a"#
+    ));
+    assert!(resp.body.contains("http://note.test/series/alice/the-book"));
+    let resp = app.get("/@alice/intro", None).await;
+    assert!(resp.body.contains("« Home"));
+    assert!(resp.body.contains(">Basics »<"));
+
+    let resp = app.get("/series/alice/the-book", None).await;
+    assert_eq!(resp.status, StatusCode::OK);
+    let intro = resp.body.find("Intro").unwrap();
+    let basics = resp.body.find("Basics").unwrap();
+    assert!(intro < basics);
+
+    // reordering updates the cached neighbours
+    assert!(!app.get("/@alice/zzz", None).await.body.contains("Intro"));
+    let json = r#"["Intro", "---\nseries: The Book\norder: 9\n---\ntext"]"#;
+    app.send(Method::POST, "/page/alice/intro", Some(&alice), Some(json))
+        .await;
+    let resp = app.get("/@alice/zzz", None).await;
+    assert!(resp.body.contains(">Intro »<"));
+    assert_eq!(
+        app.get("/series/alice/nothing", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn attachments() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let eve = app.sign_up("eve", "").await;
+    app.send(Method::PUT, "/page/alice/notes", Some(&alice), None)
+        .await;
+
+    let upload = |name: &str, cookie: &str, body: &[u8]| {
+        let request = Request::put(format!("/files/alice/notes/{name}"))
+            .header(header::COOKIE, cookie)
+            .body(Body::from(body.to_vec()))
+            .unwrap();
+        app.router.clone().oneshot(request)
+    };
+    let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    let status = |resp: Result<axum::response::Response, _>| resp.unwrap().status();
+    assert_eq!(
+        status(upload("shot.png", &eve, png).await),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        status(upload(".env", &alice, png).await),
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        status(upload("big.bin", &alice, &[0; 2048]).await),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    assert_eq!(
+        status(upload("shot.png", &alice, png).await),
+        StatusCode::OK
+    );
+    // html is served as text, whatever the name says
+    let html = b"<script>alert(1)</script>";
+    assert_eq!(
+        status(upload("page.html", &alice, html).await),
+        StatusCode::OK
+    );
+
+    let get = |uri: &str, etag: Option<&str>| {
+        let mut request = Request::get(uri);
+        if let Some(etag) = etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        app.router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+    };
+    let resp = get("/files/alice/notes/shot.png", None).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/png");
+    assert_eq!(resp.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(&body[..], png);
+    let resp = get("/files/alice/notes/shot.png", Some(&etag))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    let resp = get("/files/alice/notes/page.html", None).await.unwrap();
+    assert_eq!(
+        resp.headers()[header::CONTENT_TYPE],
+        "text/plain; charset=utf-8"
+    );
+
+    // listed in the editor
+    let resp = app.get("/@alice/notes/edit", Some(&alice)).await;
+    assert!(resp.body.contains("Attachments (2)"));
+    assert!(resp.body.contains("image/png · 16 bytes"));
+
+    // gone with the page, back with it
+    app.send(Method::DELETE, "/page/alice/notes", Some(&alice), None)
+        .await;
+    assert_eq!(
+        app.get("/files/alice/notes/shot.png", None).await.status,
+        StatusCode::NOT_FOUND
+    );
+    let resp = app
+        .send(
+            Method::DELETE,
+            "/files/alice/notes/shot.png",
+            Some(&alice),
+            None,
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn image_upload() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let eve = app.sign_up("eve", "").await;
+    app.send(Method::PUT, "/page/alice/notes", Some(&alice), None)
+        .await;
+
+    let mut png = vec![];
+    let image = image::RgbImage::from_pixel(600, 20, image::Rgb([10, 20, 30]));
+    image
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    let upload = |cookie: &str, body: Vec<u8>| {
+        let request = Request::post("/image/alice/notes")
+            .header(header::COOKIE, cookie)
+            .body(Body::from(body))
+            .unwrap();
+        app.router.clone().oneshot(request)
+    };
+
+    let resp = upload(&eve, png.clone()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let resp = upload(&alice, b"not an image".to_vec()).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // a thumbnail linking to the full image
+    let resp = upload(&alice, png).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    // a json string, nothing in it needs escaping
+    let markdown = String::from_utf8(body.to_vec()).unwrap();
+    let markdown = markdown.trim_matches('"');
+    let prefix = "[![](http://note.test/files/alice/notes/";
+    assert!(markdown.starts_with(prefix), "{markdown}");
+    assert!(markdown.ends_with(".png)"));
+    let full = markdown.rsplit("http://note.test").next().unwrap();
+    let full = full.trim_end_matches(')');
+    let request = Request::get(full).body(Body::empty()).unwrap();
+    let resp = app.router.clone().oneshot(request).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::CONTENT_TYPE], "image/png");
+
+    let resp = app.get("/@alice/notes/edit", Some(&alice)).await;
+    assert!(resp.body.contains("Attachments (2)"));
+    assert!(resp.body.contains(".thumb.png"));
+}
+
+#[tokio::test]
+async fn page_preview() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+
+    let json = r#"["---\ntags: [a]\n---\n# Draft\n\n| a |\n|---|\n| 1 |"]"#;
+    let resp = app.send(Method::POST, "/preview", None, Some(json)).await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app
+        .send(Method::POST, "/preview", Some(&alice), Some(json))
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+    assert!(resp.body.starts_with("<h1>Draft</h1>"));
+    assert!(resp.body.contains("<table>"));
+
+    // nothing was saved
+    assert_eq!(app.get("/tags/a", None).await.status, StatusCode::NOT_FOUND);
+
+    let json = format!(r#"["{}"]"#, "x".repeat(300 << 10));
+    let resp = app
+        .send(Method::POST, "/preview", Some(&alice), Some(&json))
+        .await;
+    assert_eq!(resp.status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn drafts() {
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let eve = app.sign_up("eve", "").await;
+    app.send(Method::PUT, "/page/alice/notes", Some(&alice), None)
+        .await;
+    let json = r#"["Notes", "published"]"#;
+    app.send(Method::POST, "/page/alice/notes", Some(&alice), Some(json))
+        .await;
+
+    let draft = r#"["Notes", "half written"]"#;
+    let resp = app
+        .send(Method::POST, "/draft/alice/notes", Some(&eve), Some(draft))
+        .await;
+    assert_eq!(resp.status, StatusCode::FORBIDDEN);
+    let resp = app
+        .send(
+            Method::POST,
+            "/draft/alice/notes",
+            Some(&alice),
+            Some(draft),
+        )
+        .await;
+    assert_eq!(resp.status, StatusCode::OK);
+
+    // readers, feeds and the sitemap only see what's published
+    let resp = app.get("/@alice/notes", None).await;
+    assert!(resp.body.contains("published"));
+    assert!(!resp.body.contains("half written"));
+    assert!(
+        !app.get("/feed.atom", None)
+            .await
+            .body
+            .contains("half written")
+    );
+
+    // the editor offers to resume
+    let resp = app.get("/@alice/notes/edit", Some(&alice)).await;
+    assert!(resp.body.contains("You have unpublished changes"));
+    assert!(resp.body.contains("half written"));
+
+    // discarded, or published
+    app.send(Method::DELETE, "/draft/alice/notes", Some(&alice), None)
+        .await;
+    let resp = app.get("/@alice/notes/edit", Some(&alice)).await;
+    assert!(!resp.body.contains("You have unpublished changes"));
+    app.send(
+        Method::POST,
+        "/draft/alice/notes",
+        Some(&alice),
+        Some(draft),
+    )
+    .await;
+    app.send(Method::POST, "/page/alice/notes", Some(&alice), Some(draft))
+        .await;
+    let resp = app.get("/@alice/notes/edit", Some(&alice)).await;
+    assert!(!resp.body.contains("You have unpublished changes"));
+    assert!(
+        app.get("/@alice/notes", None)
+            .await
+            .body
+            .contains("half written")
+    );
+}
+
+type Socket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// open the collaboration socket of `page`, or the status it was refused with
+async fn collab(
+    addr: std::net::SocketAddr,
+    page: &str,
+    cookie: Option<&str>,
+    origin: Option<&str>,
+) -> Result<Socket, StatusCode> {
+    use tokio_tungstenite::tungstenite::{Error, client::IntoClientRequest};
+    let mut request = format!("ws://{addr}/collab/{page}")
+        .into_client_request()
+        .unwrap();
+    for (name, value) in [(header::COOKIE, cookie), (header::ORIGIN, origin)] {
+        if let Some(value) = value {
+            request.headers_mut().insert(name, value.parse().unwrap());
+        }
+    }
+    match tokio_tungstenite::connect_async(request).await {
+        Ok((socket, _)) => Ok(socket),
+        Err(Error::Http(resp)) => Err(StatusCode::from_u16(resp.status().as_u16()).unwrap()),
+        Err(e) => panic!("connect: {e}"),
+    }
+}
+
+/// next message of `socket`, as json
+async fn receive(socket: &mut Socket) -> serde_json::Value {
+    use futures_util::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+    loop {
+        let message = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+            .await
+            .expect("no message")
+            .unwrap()
+            .unwrap();
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+/// next message of `socket` of type `kind`, skipping others
+async fn receive_kind(socket: &mut Socket, kind: &str) -> serde_json::Value {
+    loop {
+        let message = receive(socket).await;
+        if message["type"] == kind {
+            return message;
+        }
+    }
+}
+
+#[tokio::test]
+async fn collaborative_editing() {
+    use futures_util::SinkExt;
+    let app = app();
+    let alice = app.sign_up("alice", "").await;
+    let bob = app.sign_up("bob", "alice").await;
+    let eve = app.sign_up("eve", "").await;
+    app.send(Method::PUT, "/page/alice/notes", Some(&alice), None)
+        .await;
+    let json = r#"["Notes", "hello"]"#;
+    app.send(Method::POST, "/page/alice/notes", Some(&alice), Some(json))
+        .await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(axum::serve(listener, app.router.clone()).into_future());
+
+    // authorized like the editor, and only from the site's own pages
+    let page = "alice/notes";
+    assert_eq!(
+        collab(addr, page, None, None).await.err(),
+        Some(StatusCode::FORBIDDEN)
+    );
+    assert_eq!(
+        collab(addr, page, Some(&eve), None).await.err(),
+        Some(StatusCode::FORBIDDEN)
+    );
+    let evil = Some("http://evil.test");
+    assert_eq!(
+        collab(addr, page, Some(&alice), evil).await.err(),
+        Some(StatusCode::FORBIDDEN)
+    );
+    assert_eq!(
+        collab(addr, "alice/missing", Some(&alice), None)
+            .await
+            .err(),
+        Some(StatusCode::NOT_FOUND)
+    );
+
+    // both join, and see each other
+    let origin = Some("http://note.test");
+    let mut a = collab(addr, page, Some(&alice), origin).await.unwrap();
+    let init = receive(&mut a).await;
+    assert_eq!(init["type"], "init");
+    assert_eq!(
+        (&init["markdown"], &init["rev"]),
+        (&"hello".into(), &0.into())
+    );
+    let mut b = collab(addr, page, Some(&bob), None).await.unwrap();
+    let init = receive(&mut b).await;
+    assert_eq!(init["peers"][0]["user"], "alice");
+    assert_eq!(receive(&mut a).await["user"], "bob");
+
+    // both edit revision 0, bob's edit is moved past alice's
+    let a_edit = r#"{"type": "edit", "rev": 0, "op": [5, " world"], "cursor": [11, 11]}"#;
+    let b_edit = r#"{"type": "edit", "rev": 0, "op": ["Oh, ", 5], "cursor": [4, 4]}"#;
+    a.send(a_edit.into()).await.unwrap();
+    assert_eq!(receive_kind(&mut a, "ack").await["rev"], 1);
+    b.send(b_edit.into()).await.unwrap();
+    let seen = receive_kind(&mut b, "edit").await;
+    assert_eq!(seen["op"], serde_json::json!([5, " world"]));
+    assert_eq!(receive_kind(&mut b, "ack").await["rev"], 2);
+    let seen = receive_kind(&mut a, "edit").await;
+    assert_eq!(seen["op"], serde_json::json!(["Oh, ", 11]));
+    assert_eq!(seen["rev"], 2);
+    let title = r#"{"type": "title", "title": "Shared"}"#;
+    b.send(title.into()).await.unwrap();
+    assert_eq!(receive_kind(&mut a, "title").await["title"], "Shared");
+    // a blank title isn't taken, its sender is told
+    let blank = r#"{"type": "title", "title": "  "}"#;
+    b.send(blank.into()).await.unwrap();
+    assert_eq!(
+        receive_kind(&mut b, "error").await["title"],
+        "Invalid Title"
+    );
+
+    // the last one out saves
+    a.close(None).await.unwrap();
+    b.close(None).await.unwrap();
+    let mut body = String::new();
+    for _ in 0..50 {
+        body = app.get("/@alice/notes", None).await.body;
+        if body.contains("Oh, hello world") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(body.contains("Oh, hello world"), "{body}");
+    assert!(body.contains("Shared"));
+}